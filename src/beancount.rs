@@ -1,17 +1,307 @@
 use crate::Error;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::read_to_string;
-use std::path::Path;
-use tower_lsp::lsp_types::{Location, Position, Range, Url};
-use tree_sitter::{Node, TreeCursor};
+use std::path::{Component, Path, PathBuf};
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, Location, Position, Range, TextEdit, Url,
+};
+use tree_sitter::{Node, Point, Tree, TreeCursor};
+
+/// Path of an `include` or `document` directive, resolved relative to its ledger.
+pub struct DirectivePath {
+    /// Range of the quoted path.
+    pub range: Range,
+    /// Normalized path.
+    pub path: PathBuf,
+    /// Glob pattern if the path is one, with the directory of the ledger escaped.
+    pub pattern: Option<String>,
+}
+
+/// An include directive and the ledger files it refers to.
+pub struct Include {
+    pub directive: DirectivePath,
+    /// Included ledger files, i.e. the expanded path.
+    pub files: Vec<Url>,
+}
+
+/// Names of all beancount options and the valid values of enumerated options.
+pub const OPTIONS: [(&str, &[&str]); 28] = [
+    ("title", &[]),
+    ("name_assets", &[]),
+    ("name_liabilities", &[]),
+    ("name_equity", &[]),
+    ("name_income", &[]),
+    ("name_expenses", &[]),
+    ("account_previous_balances", &[]),
+    ("account_previous_earnings", &[]),
+    ("account_previous_conversions", &[]),
+    ("account_current_earnings", &[]),
+    ("account_current_conversions", &[]),
+    ("account_unrealized_gains", &[]),
+    ("account_rounding", &[]),
+    ("conversion_currency", &[]),
+    ("inferred_tolerance_default", &[]),
+    ("inferred_tolerance_multiplier", &[]),
+    ("infer_tolerance_from_cost", &["TRUE", "FALSE"]),
+    ("documents", &[]),
+    ("operating_currency", &[]),
+    ("render_commas", &["TRUE", "FALSE"]),
+    ("plugin_processing_mode", &["default", "raw"]),
+    ("long_string_maxlines", &[]),
+    (
+        "booking_method",
+        &[
+            "STRICT",
+            "STRICT_WITH_SIZE",
+            "FIFO",
+            "LIFO",
+            "HIFO",
+            "AVERAGE",
+            "NONE",
+        ],
+    ),
+    ("allow_pipe_separator", &["TRUE", "FALSE"]),
+    (
+        "allow_deprecated_none_for_tags_and_links",
+        &["TRUE", "FALSE"],
+    ),
+    ("insert_pythonpath", &["TRUE", "FALSE"]),
+    ("tolerance", &[]),
+    ("default_tolerance", &[]),
+];
+
+/// Options naming the root accounts and their default names.
+const ROOT_ACCOUNTS: [(&str, &str); 5] = [
+    ("name_assets", "Assets"),
+    ("name_liabilities", "Liabilities"),
+    ("name_equity", "Equity"),
+    ("name_income", "Income"),
+    ("name_expenses", "Expenses"),
+];
+
+/// Kind of an indexed symbol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Account,
+    Commodity,
+    Payee,
+    Tag,
+    Link,
+    MetadataKey,
+}
+
+/// Definition or occurrence of a symbol in a ledger.
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    /// Name without quotes and `#` or `^` prefixes.
+    pub name: String,
+    pub range: Range,
+    /// Kind of the enclosing directive, e.g. `transaction` or `open`.
+    pub directive: &'static str,
+    /// Date of the enclosing directive.
+    pub date: Option<String>,
+    /// The symbol is defined here, i.e. an account by `open` or a commodity by `commodity`.
+    pub definition: bool,
+}
+
+/// A `key: value` metadata entry of a directive or posting.
+#[derive(Clone, Debug)]
+pub struct Metadata {
+    pub key: String,
+    /// Value as written, i.e. strings keep their quotes.
+    pub value: String,
+    /// Kind of the enclosing directive, e.g. `transaction` or `open`.
+    pub directive: &'static str,
+}
+
+/// A transaction.
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    pub date: String,
+    /// Flag such as `*`, `!` or `txn`.
+    pub flag: String,
+    /// Payee without quotes.
+    pub payee: Option<String>,
+    /// Narration without quotes.
+    pub narration: Option<String>,
+    /// Tags without `#` prefix.
+    pub tags: Vec<String>,
+    /// Tags pushed by `pushtag` directives that are not among `tags`, without `#` prefix.
+    pub pushed_tags: Vec<String>,
+    pub postings: Vec<Posting>,
+}
+
+/// A posting of a transaction.
+#[derive(Clone, Debug)]
+pub struct Posting {
+    pub account: String,
+    /// Number as written, if the posting has an amount.
+    pub number: Option<String>,
+    pub currency: Option<String>,
+    /// The posting has a cost or price, so its weight differs from its units.
+    pub weighted: bool,
+    pub range: Range,
+}
+
+/// A `commodity` directive.
+#[derive(Clone, Debug)]
+pub struct Commodity {
+    pub currency: String,
+    pub date: String,
+    /// Value of the `name` metadata without quotes.
+    pub name: Option<String>,
+}
+
+/// A decimal number as written in a ledger, e.g. `-1,234.50`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+/// An `open` directive.
+#[derive(Clone, Debug)]
+pub struct Open {
+    pub account: String,
+    pub date: String,
+    /// Constraint currencies, empty if the account accepts any currency.
+    pub currencies: Vec<String>,
+    pub booking: Option<String>,
+}
+
+/// An `option` directive.
+#[derive(Clone, Debug)]
+pub struct LedgerOption {
+    /// Name without quotes.
+    pub key: String,
+    /// Value without quotes.
+    pub value: String,
+    /// Range of the quoted name.
+    pub range: Range,
+}
+
+/// A `close` directive.
+#[derive(Clone, Debug)]
+pub struct Close {
+    pub account: String,
+    pub date: String,
+}
+
+/// An account and the directives that opened and closed it.
+#[derive(Default)]
+pub struct Account<'a> {
+    pub open: Option<&'a Open>,
+    pub close: Option<&'a Close>,
+}
+
+impl Account<'_> {
+    /// Returns `true` if postings on `date` are allowed, i.e. the account is not opened later
+    /// and not closed earlier. Accounts without `open` directive are considered open.
+    pub fn is_open(&self, date: &str) -> bool {
+        self.open.is_none_or(|open| open.date.as_str() <= date)
+            && self.close.is_none_or(|close| date <= close.date.as_str())
+    }
+}
+
+impl Decimal {
+    /// Parse a plain number, i.e. without arithmetic expressions.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.replace(',', "");
+        let (integer, fraction) = text.split_once('.').unwrap_or((&text, ""));
+
+        if !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
 
+        Some(Self {
+            mantissa: format!("{}{}", integer, fraction).parse().ok()?,
+            scale: fraction.len() as u32,
+        })
+    }
+
+    /// Rescale to `scale` decimal places, or `None` on overflow.
+    fn rescale(self, scale: u32) -> Option<Self> {
+        Some(Self {
+            mantissa: self
+                .mantissa
+                .checked_mul(10i128.checked_pow(scale - self.scale)?)?,
+            scale,
+        })
+    }
+
+    /// Sum of both numbers, or `None` on overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+
+        Some(Self {
+            mantissa: self
+                .rescale(scale)?
+                .mantissa
+                .checked_add(other.rescale(scale)?.mantissa)?,
+            scale,
+        })
+    }
+
+    /// Negated number, or `None` on overflow.
+    pub fn checked_neg(self) -> Option<Self> {
+        Some(Self {
+            mantissa: self.mantissa.checked_neg()?,
+            scale: self.scale,
+        })
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let scale = self.scale as usize;
+
+        // Pad with zeros so that there is at least one integer digit.
+        let digits = format!(
+            "{:0width$}",
+            self.mantissa.unsigned_abs(),
+            width = scale + 1
+        );
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+
+        match scale {
+            0 => write!(f, "{}{}", sign, integer),
+            _ => write!(f, "{}{}.{}", sign, integer, fraction),
+        }
+    }
+}
+
+/// Usage of a symbol outside of its definitions.
+#[derive(Default)]
+pub struct Usage<'a> {
+    pub count: usize,
+    /// Date of the most recent use.
+    pub last: Option<&'a str>,
+}
+
+/// Index of a single ledger file.
+#[derive(Default)]
+pub struct Ledger {
+    pub symbols: Vec<Symbol>,
+    pub metadata: Vec<Metadata>,
+    pub transactions: Vec<Transaction>,
+    pub options: Vec<LedgerOption>,
+    pub commodities: Vec<Commodity>,
+    pub opens: Vec<Open>,
+    pub closes: Vec<Close>,
+    pub includes: Vec<Include>,
+    /// The ledger could not be parsed without errors.
+    pub has_errors: bool,
+}
+
+/// Index of root ledgers and all ledgers they include.
 #[derive(Default)]
 pub struct Data {
-    pub commodities: HashMap<String, Location>,
-    pub accounts: HashSet<String>,
-    pub currencies: HashSet<String>,
-    pub payees: HashSet<String>,
-    pub text: String,
+    roots: HashSet<Url>,
+    files: HashMap<Url, Ledger>,
+    /// Included ledgers that failed to load and the reason why.
+    failures: HashMap<Url, String>,
 }
 
 fn node_text_by_field_name<'a>(
@@ -25,488 +315,2710 @@ fn node_text_by_field_name<'a>(
         .utf8_text(bytes)?)
 }
 
-impl Data {
-    pub fn new(uri: &Url) -> Result<Self, Error> {
-        Data::read(uri, Self::default())
+pub fn parse(text: &str, old_tree: Option<&Tree>) -> Result<Tree, Error> {
+    let mut parser = tree_sitter::Parser::new();
+    parser.set_language(tree_sitter_beancount::language())?;
+    parser.parse(text, old_tree).ok_or(Error::TreeParseError)
+}
+
+/// LSP position of a tree-sitter point at byte `offset`, counting UTF-16 code units.
+fn position(text: &str, point: Point, offset: usize) -> Position {
+    let character = text
+        .get(offset - point.column..offset)
+        .map_or(point.column, |line| line.encode_utf16().count());
+
+    Position {
+        line: point.row as u32,
+        character: character as u32,
     }
+}
 
-    /// Recursively read ledgers, i.e. those included.
-    fn read(uri: &Url, data: Self) -> Result<Self, Error> {
-        let file_path = uri.to_file_path().map_err(|_| Error::UriToPathConversion)?;
+/// LSP range of a node within `text`.
+pub fn range(node: &Node, text: &str) -> Range {
+    Range {
+        start: position(text, node.start_position(), node.start_byte()),
+        end: position(text, node.end_position(), node.end_byte()),
+    }
+}
 
-        let text = read_to_string(&file_path)?;
-        let bytes = text.as_bytes();
+/// Payee string of a transaction, which only exists if the transaction has two strings.
+pub fn payee<'a>(txn_strings: &Node<'a>) -> Option<Node<'a>> {
+    match txn_strings.named_child_count() {
+        2 => txn_strings.named_child(0),
+        _ => None,
+    }
+}
 
-        let mut parser = tree_sitter::Parser::new();
-        parser.set_language(tree_sitter_beancount::language())?;
-        let tree = parser
-            .parse(&text, None)
-            .ok_or_else(|| Error::TreeParseError)?;
-        let mut cursor = tree.root_node().walk();
+/// Narration string of a transaction, which is always the last string.
+pub fn narration<'a>(txn_strings: &Node<'a>) -> Option<Node<'a>> {
+    match txn_strings.named_child_count() {
+        0 => None,
+        count => txn_strings.named_child(count - 1),
+    }
+}
 
-        let mut commodities = HashMap::new();
+/// Collect symbols of `node` and its descendants, all part of `directive`.
+fn collect_symbols(
+    node: &Node,
+    directive: &Node,
+    date: Option<&str>,
+    text: &str,
+    symbols: &mut Vec<Symbol>,
+) {
+    let mut push = |node: &Node, kind: SymbolKind, definition: bool| {
+        if let Ok(name) = node.utf8_text(text.as_bytes()) {
+            symbols.push(Symbol {
+                kind,
+                name: name
+                    .trim_start_matches(['#', '^'])
+                    .trim_matches('"')
+                    .to_string(),
+                range: range(node, text),
+                directive: directive.kind(),
+                date: date.map(str::to_string),
+                definition,
+            });
+        }
+    };
 
-        for commodity in tree
-            .root_node()
-            .children(&mut cursor)
-            .filter(|c| c.kind() == "commodity")
-        {
-            let currency = node_text_by_field_name(&commodity, "currency", bytes)?;
-            let start = commodity.start_position();
-            let end = commodity.end_position();
-
-            let range = Range {
-                start: Position {
-                    line: start.row as u32,
-                    character: start.column as u32,
-                },
-                end: Position {
-                    line: end.row as u32,
-                    character: end.column as u32,
-                },
-            };
+    match node.kind() {
+        "account" => push(node, SymbolKind::Account, directive.kind() == "open"),
+        "currency" => push(node, SymbolKind::Commodity, directive.kind() == "commodity"),
+        "tag" => push(node, SymbolKind::Tag, false),
+        "link" => push(node, SymbolKind::Link, false),
+        "key" => push(node, SymbolKind::MetadataKey, false),
+        "txn_strings" => {
+            if let Some(payee) = payee(node) {
+                push(&payee, SymbolKind::Payee, false);
+            }
+        }
+        _ => {
+            let mut cursor = node.walk();
 
-            let location = Location {
-                uri: (*uri).clone(),
-                range,
-            };
+            for child in node.named_children(&mut cursor) {
+                collect_symbols(&child, directive, date, text, symbols);
+            }
+        }
+    }
+}
+
+/// Symbols of all directives of a parsed ledger.
+pub fn symbols(tree: &Tree, text: &str) -> Vec<Symbol> {
+    let mut cursor = tree.root_node().walk();
+    let mut symbols = Vec::new();
+
+    for directive in tree.root_node().named_children(&mut cursor) {
+        let date = directive
+            .child_by_field_name("date")
+            .and_then(|date| date.utf8_text(text.as_bytes()).ok());
+
+        collect_symbols(&directive, &directive, date, text, &mut symbols);
+    }
+
+    symbols
+}
+
+/// Collect metadata of `node` and its descendants, all part of `directive`.
+fn collect_metadata(node: &Node, directive: &Node, bytes: &[u8], metadata: &mut Vec<Metadata>) {
+    let mut cursor = node.walk();
 
-            commodities.insert(currency.to_string(), location);
+    for child in node.named_children(&mut cursor) {
+        if child.kind() != "key_value" {
+            collect_metadata(&child, directive, bytes, metadata);
+            continue;
         }
 
-        let mut accounts = HashSet::new();
-        let mut currencies = HashSet::new();
-        let mut payees = HashSet::new();
+        let key = child
+            .named_child(0)
+            .and_then(|key| key.utf8_text(bytes).ok());
+        let value = child
+            .named_child(1)
+            .and_then(|value| value.utf8_text(bytes).ok());
+
+        if let (Some(key), Some(value)) = (key, value) {
+            metadata.push(Metadata {
+                key: key.to_string(),
+                value: value.to_string(),
+                directive: directive.kind(),
+            });
+        }
+    }
+}
 
-        let transactions = tree
-            .root_node()
-            .children(&mut cursor)
-            .filter(|c| c.kind() == "transaction")
-            .collect::<Vec<_>>();
+/// Top-level directive the line `row` belongs to, i.e. the last one starting at or before it.
+pub fn directive_at(tree: &Tree, row: usize) -> Option<Node<'_>> {
+    let root = tree.root_node();
+    let mut directive = None;
 
-        for transaction in transactions {
-            if let Some(txn_strings) = transaction.child_by_field_name("txn_strings") {
-                if let Some(payee) = txn_strings.children(&mut cursor).next() {
-                    payees.insert(
-                        payee
-                            .utf8_text(bytes)?
-                            .trim_end_matches('"')
-                            .trim_start_matches('"')
-                            .to_string(),
-                    );
-                }
-            }
+    for index in 0..root.named_child_count() {
+        match root.named_child(index) {
+            Some(node) if node.start_position().row > row => break,
+            Some(node) if node.kind() != "ERROR" => directive = Some(node),
+            _ => {}
+        }
+    }
 
-            let lists = transaction
-                .children_by_field_name("posting_or_kv_list", &mut cursor)
-                .collect::<Vec<_>>();
+    directive
+}
 
-            for list in lists {
-                let postings = list
-                    .children(&mut cursor)
-                    .filter(|c| c.kind() == "posting")
-                    .collect::<Vec<_>>();
+fn read_open(node: &Node, bytes: &[u8]) -> Result<Open, Error> {
+    let mut cursor = node.walk();
+
+    let currencies = node
+        .named_children(&mut cursor)
+        .filter(|c| c.kind() == "currency")
+        .map(|c| Ok(c.utf8_text(bytes)?.to_string()))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let booking = node
+        .named_children(&mut cursor)
+        .find(|c| c.kind() == "opt_booking" || c.kind() == "string")
+        .map(|c| Ok::<_, Error>(c.utf8_text(bytes)?.trim_matches('"').to_string()))
+        .transpose()?;
+
+    Ok(Open {
+        account: node_text_by_field_name(node, "account", bytes)?.to_string(),
+        date: node_text_by_field_name(node, "date", bytes)?.to_string(),
+        currencies,
+        booking,
+    })
+}
 
-                for posting in postings {
-                    for account in posting.children_by_field_name("account", &mut cursor) {
-                        accounts.insert(account.utf8_text(bytes)?.to_string());
-                    }
+fn read_commodity(node: &Node, bytes: &[u8]) -> Result<Commodity, Error> {
+    let mut metadata = Vec::new();
+    collect_metadata(node, node, bytes, &mut metadata);
+
+    Ok(Commodity {
+        currency: node_text_by_field_name(node, "currency", bytes)?.to_string(),
+        date: node_text_by_field_name(node, "date", bytes)?.to_string(),
+        name: metadata
+            .into_iter()
+            .find(|metadata| metadata.key == "name")
+            .map(|metadata| metadata.value.trim_matches('"').to_string()),
+    })
+}
 
-                    let amounts = posting
-                        .children_by_field_name("amount", &mut cursor)
-                        .collect::<Vec<_>>();
+fn read_option(node: &Node, text: &str) -> Result<LedgerOption, Error> {
+    let key = node.child_by_field_name("key").ok_or(Error::InvalidState)?;
+
+    Ok(LedgerOption {
+        key: key
+            .utf8_text(text.as_bytes())?
+            .trim_matches('"')
+            .to_string(),
+        value: node_text_by_field_name(node, "value", text.as_bytes())?
+            .trim_matches('"')
+            .to_string(),
+        range: range(&key, text),
+    })
+}
 
-                    for amount in amounts {
-                        for currency in amount
-                            .children(&mut cursor)
-                            .filter(|c| c.kind() == "currency")
-                        {
-                            currencies.insert(currency.utf8_text(bytes)?.to_string());
-                        }
-                    }
+fn read_close(node: &Node, bytes: &[u8]) -> Result<Close, Error> {
+    Ok(Close {
+        account: node_text_by_field_name(node, "account", bytes)?.to_string(),
+        date: node_text_by_field_name(node, "date", bytes)?.to_string(),
+    })
+}
+
+/// Collect tags of a transaction header, i.e. all tags outside its postings and metadata.
+fn collect_tags(node: &Node, bytes: &[u8], tags: &mut Vec<String>) {
+    let mut cursor = node.walk();
+
+    for child in node.named_children(&mut cursor) {
+        match child.kind() {
+            "tag" => tags.extend(
+                child
+                    .utf8_text(bytes)
+                    .ok()
+                    .map(|tag| tag.trim_start_matches('#').to_string()),
+            ),
+            "posting_or_kv_list" => {}
+            _ => collect_tags(&child, bytes, tags),
+        }
+    }
+}
+
+fn read_posting(node: &Node, text: &str) -> Result<Posting, Error> {
+    let bytes = text.as_bytes();
+    let amount = node.child_by_field_name("amount");
+
+    let part = |index: usize| -> Result<Option<String>, Error> {
+        Ok(match amount.and_then(|amount| amount.named_child(index)) {
+            Some(part) => Some(part.utf8_text(bytes)?.to_string()),
+            None => None,
+        })
+    };
+
+    Ok(Posting {
+        account: node_text_by_field_name(node, "account", bytes)?.to_string(),
+        number: part(0)?,
+        currency: part(1)?,
+        weighted: node.child_by_field_name("cost_spec").is_some()
+            || node.child_by_field_name("price_annotation").is_some(),
+        range: range(node, text),
+    })
+}
+
+fn read_transaction(node: &Node, text: &str) -> Result<Transaction, Error> {
+    let bytes = text.as_bytes();
+    let txn_strings = node.child_by_field_name("txn_strings");
+
+    let string = |string: Option<Node>| -> Result<Option<String>, Error> {
+        Ok(match string {
+            Some(string) => Some(string.utf8_text(bytes)?.trim_matches('"').to_string()),
+            None => None,
+        })
+    };
+
+    let mut tags = Vec::new();
+    collect_tags(node, bytes, &mut tags);
+
+    let postings = match node.child_by_field_name("posting_or_kv_list") {
+        Some(list) => {
+            let mut cursor = list.walk();
+
+            let postings = list
+                .named_children(&mut cursor)
+                .filter(|child| child.kind() == "posting")
+                .map(|posting| read_posting(&posting, text))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            postings
+        }
+        None => Vec::new(),
+    };
+
+    Ok(Transaction {
+        date: node_text_by_field_name(node, "date", bytes)?.to_string(),
+        flag: node_text_by_field_name(node, "txn", bytes)?.to_string(),
+        payee: string(txn_strings.as_ref().and_then(payee))?,
+        narration: string(txn_strings.as_ref().and_then(narration))?,
+        tags,
+        pushed_tags: Vec::new(),
+        postings,
+    })
+}
+
+impl DirectivePath {
+    /// Expand the path to the files it refers to. Like beancount, glob patterns are expanded to
+    /// all matching files in sorted order, while plain paths are returned as is.
+    pub fn expand(&self) -> Vec<PathBuf> {
+        let pattern = match &self.pattern {
+            Some(pattern) => pattern,
+            None => return vec![self.path.clone()],
+        };
+
+        let mut paths: Vec<PathBuf> = glob::glob(pattern)
+            .map(|paths| paths.flatten().filter(|path| path.is_file()).collect())
+            .unwrap_or_default();
+
+        paths.sort();
+        paths
+    }
+}
+
+/// Lexically normalize `path` by dropping `.` and resolving `..` components, so that a ledger
+/// has the same URI no matter which include reaches it. Symbolic links are kept as they are,
+/// just like in the URIs of the client.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
                 }
-            }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            component => normalized.push(component),
         }
+    }
+
+    normalized
+}
 
-        let mut data = data;
+/// URI of the file at the normalized `path`.
+pub fn file_url(path: &Path) -> Result<Url, Error> {
+    Url::from_file_path(normalize(path)).map_err(|_| Error::UriToPathConversion)
+}
 
-        // Descend into included ledgers, ignore all that fail to load.
-        let includes = tree
-            .root_node()
-            .children(&mut cursor)
-            .filter(|c| c.kind() == "include")
-            .collect::<Vec<_>>();
+/// Normalized URI of a file as sent by the client.
+pub fn normalize_url(uri: &Url) -> Url {
+    match uri.to_file_path() {
+        Ok(path) if normalize(&path) != path => file_url(&path).unwrap_or_else(|_| uri.clone()),
+        _ => uri.clone(),
+    }
+}
+
+/// Resolve the paths of all `kind` directives, i.e. `include` or `document`, of a ledger
+/// located at `file_path`. Paths are normalized, glob patterns are not expanded. Only the path
+/// as written can make a glob pattern, never the directory of the ledger.
+pub fn paths(tree: &Tree, text: &str, file_path: &Path, kind: &str) -> Vec<DirectivePath> {
+    let mut cursor = tree.root_node().walk();
+
+    let directives = tree
+        .root_node()
+        .children(&mut cursor)
+        .filter(|c| c.kind() == kind)
+        .collect::<Vec<_>>();
 
-        let include_datas = includes.into_iter().filter_map(|include| {
-            let node = include
+    directives
+        .into_iter()
+        .filter_map(|directive| {
+            let node = directive
                 .children(&mut cursor)
                 .find(|c| c.kind() == "string")?;
 
             let filename = node
-                .utf8_text(bytes)
-                .unwrap()
+                .utf8_text(text.as_bytes())
+                .ok()?
                 .trim_start_matches('"')
                 .trim_end_matches('"');
 
-            let path = Path::new(filename);
+            let directory = match file_path.parent() {
+                Some(parent) if file_path.is_absolute() => parent,
+                _ => Path::new(""),
+            };
 
-            let path = if path.is_absolute() {
-                path.to_path_buf()
-            } else if file_path.is_absolute() {
-                file_path.parent().unwrap().join(path)
+            let pattern = if filename.contains(['*', '?', '[']) {
+                let directory = glob::Pattern::escape(directory.to_str()?);
+                Some(
+                    normalize(&Path::new(&directory).join(filename))
+                        .to_str()?
+                        .to_string(),
+                )
             } else {
-                path.to_path_buf()
+                None
             };
 
-            let uri = Url::from_file_path(path).unwrap();
-            Some(Data::read(&uri, Data::default()))
-        });
+            Some(DirectivePath {
+                range: range(&node, text),
+                path: normalize(&directory.join(filename)),
+                pattern,
+            })
+        })
+        .collect()
+}
 
-        for include_data in include_datas.flatten() {
-            data.commodities
-                .extend(include_data.commodities.into_iter());
-            data.accounts.extend(include_data.accounts.into_iter());
-            data.currencies.extend(include_data.currencies.into_iter());
-        }
+/// Collect all ledger files below `dir`, skipping hidden and unreadable entries. Symbolic links
+/// to directories are not followed, which also rules out loops.
+fn ledger_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
 
-        data.commodities.extend(commodities.into_iter());
-        data.accounts.extend(accounts.into_iter());
-        data.currencies.extend(currencies.into_iter());
-        data.payees.extend(payees.into_iter());
-        data.text = text; // TODO: yeah ...
+    for entry in entries.flatten() {
+        let path = entry.path();
 
-        Ok(data)
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| !name.starts_with('.'))
+            .is_none()
+        {
+            continue;
+        }
+
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        if metadata.is_dir() {
+            ledger_files(&path, files);
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("beancount") | Some("bean")
+        ) {
+            files.push(path);
+        }
     }
 }
 
-fn reformat_postings(postings: &Node, text: &str) -> Result<String, Error> {
-    let mut cursor = postings.walk();
-    let bytes = text.as_bytes();
+/// Map each file included by a ledger below `dir` to the first ledger including it.
+pub fn included_by(dir: &Path) -> Result<HashMap<PathBuf, PathBuf>, Error> {
+    let mut files = Vec::new();
+    ledger_files(&normalize(dir), &mut files);
+    files.sort();
 
-    let comments = postings
-        .children(&mut cursor)
-        .filter(|p| p.kind() == "comment")
-        .map(|p| Ok::<_, Error>(format!("  {}\n", p.utf8_text(bytes)?)))
-        .collect::<Result<Vec<_>, _>>()?;
+    let mut included_by = HashMap::new();
 
-    let postings = postings
-        .children(&mut cursor)
-        .filter(|p| p.kind() == "posting")
-        .collect::<Vec<_>>();
+    for file in files {
+        let text = match read_to_string(&file) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
 
-    let formatted = postings
-        .into_iter()
-        .map(|p| {
-            let account = p
-                .child_by_field_name("account")
-                .ok_or_else(|| Error::InvalidState)?
-                .utf8_text(bytes)?;
+        let tree = parse(&text, None)?;
 
-            let amount = {
-                match p.child_by_field_name("amount") {
-                    Some(amount) => {
-                        let mut amount_children = amount.children(&mut cursor);
+        for include in paths(&tree, &text, &file, "include")
+            .iter()
+            .flat_map(DirectivePath::expand)
+        {
+            included_by.entry(include).or_insert_with(|| file.clone());
+        }
+    }
 
-                        assert_eq!(amount_children.len(), 2);
+    Ok(included_by)
+}
 
-                        let number = amount_children
-                            .next()
-                            .ok_or_else(|| Error::InvalidState)?
-                            .utf8_text(bytes)?;
+/// Find the ledger that transitively includes `uri` according to `included_by` and is not
+/// included by any other ledger itself. If no ledger includes `uri`, it is its own root.
+pub fn find_root(uri: &Url, included_by: &HashMap<PathBuf, PathBuf>) -> Result<Url, Error> {
+    let file_path = uri.to_file_path().map_err(|_| Error::UriToPathConversion)?;
+    let mut root = normalize(&file_path);
+    let mut visited = HashSet::new();
 
-                        let width = 50 - 4 - account.len();
+    while let Some(parent) = included_by.get(&root) {
+        if !visited.insert(root.clone()) {
+            break;
+        }
 
-                        let currency = amount_children
-                            .next()
-                            .ok_or_else(|| Error::InvalidState)?
-                            .utf8_text(bytes)?;
+        root = parent.clone();
+    }
 
-                        // We want to align so that the number period is always at column position 50. Hence we
-                        // have to pad with 50 - 2 spaces before account - 1 space after account - 1 period -
-                        // length of account.
-                        match number.find('.') {
-                            Some(position) => {
-                                let numerator = &number[..position];
-                                let denominator = &number[position + 1..];
-                                format!(
-                                    " {:>width$}.{} {}",
-                                    numerator,
-                                    denominator,
-                                    currency,
-                                    width = width
-                                )
-                            }
-                            None => format!(" {:>width$} {}", number, currency, width = width),
+    file_url(&root)
+}
+
+impl Ledger {
+    /// Read a single ledger without descending into its includes.
+    fn read(uri: &Url) -> Result<Self, Error> {
+        let file_path = uri.to_file_path().map_err(|_| Error::UriToPathConversion)?;
+
+        let text = read_to_string(&file_path)?;
+        let bytes = text.as_bytes();
+        let tree = parse(&text, None)?;
+        let mut cursor = tree.root_node().walk();
+
+        let symbols = symbols(&tree, &text);
+        let mut metadata = Vec::new();
+        let mut transactions = Vec::new();
+        let mut options = Vec::new();
+        let mut commodities = Vec::new();
+        let mut opens = Vec::new();
+        let mut closes = Vec::new();
+        let mut pushed_tags = Vec::new();
+
+        for directive in tree.root_node().named_children(&mut cursor) {
+            collect_metadata(&directive, &directive, bytes, &mut metadata);
+
+            match directive.kind() {
+                "open" => opens.extend(read_open(&directive, bytes).ok()),
+                "close" => closes.extend(read_close(&directive, bytes).ok()),
+                "transaction" => {
+                    if let Ok(mut transaction) = read_transaction(&directive, &text) {
+                        transaction.pushed_tags = pushed_tags
+                            .iter()
+                            .filter(|tag| !transaction.tags.contains(tag))
+                            .cloned()
+                            .collect();
+
+                        transactions.push(transaction);
+                    }
+                }
+                "pushtag" => collect_tags(&directive, bytes, &mut pushed_tags),
+                "poptag" => {
+                    let mut popped = Vec::new();
+                    collect_tags(&directive, bytes, &mut popped);
+
+                    for tag in popped {
+                        if let Some(index) = pushed_tags.iter().rposition(|other| *other == tag) {
+                            pushed_tags.remove(index);
                         }
                     }
-                    None => "".to_string(),
                 }
-            };
-
-            let cost = match p.child_by_field_name("cost_spec") {
-                Some(cost) => format!(" {}", cost.utf8_text(bytes)?),
-                None => "".to_string(),
-            };
-
-            let comment = match p.child_by_field_name("comment") {
-                Some(comment) => format!("  {}", comment.utf8_text(bytes)?),
-                None => "".to_string(),
-            };
+                "option" => options.extend(read_option(&directive, &text).ok()),
+                "commodity" => commodities.extend(read_commodity(&directive, bytes).ok()),
+                _ => {}
+            }
+        }
 
-            Ok::<_, Error>(format!("  {}{}{}{}", account, amount, cost, comment))
+        let includes = paths(&tree, &text, &file_path, "include")
+            .into_iter()
+            .map(|directive| Include {
+                files: directive
+                    .expand()
+                    .into_iter()
+                    .filter_map(|path| file_url(&path).ok())
+                    .collect(),
+                directive,
+            })
+            .collect();
+
+        Ok(Self {
+            symbols,
+            metadata,
+            transactions,
+            options,
+            commodities,
+            opens,
+            closes,
+            includes,
+            has_errors: tree.root_node().has_error(),
         })
-        .collect::<Result<Vec<_>, _>>()?;
+    }
 
-    Ok(format!("{}{}", comments.join("\n"), formatted.join("\n")))
+    /// All ledger files included by this one.
+    pub fn included(&self) -> impl Iterator<Item = &Url> {
+        self.includes
+            .iter()
+            .flat_map(|include| include.files.iter())
+    }
+
+    /// Returns `true` if a glob pattern of an include directive matches `path`.
+    fn globs(&self, path: &Path) -> bool {
+        self.includes
+            .iter()
+            .filter_map(|include| glob::Pattern::new(include.directive.pattern.as_deref()?).ok())
+            .any(|glob| glob.matches_path(path))
+    }
 }
 
-fn reformat_top_level(cursor: &mut TreeCursor, text: &str) -> Result<String, Error> {
-    let node = cursor.node();
-    let bytes = text.as_bytes();
-    let end_point = node.range().end_point;
+/// Count `names` and order them by number of occurrences, most frequent first.
+fn rank<'a, I: Iterator<Item = &'a str>>(names: I) -> Vec<(&'a str, usize)> {
+    let mut counts = HashMap::new();
 
-    let newlines = |cursor: &TreeCursor| -> String {
-        let current = cursor.node().range();
-        "\n".repeat(current.start_point.row - end_point.row + 1)
-    };
+    for name in names {
+        *counts.entry(name).or_insert(0) += 1;
+    }
 
-    let formatted = match node.kind() {
-        "file" => {
-            if cursor.goto_first_child() {
-                reformat_top_level(cursor, text)?
+    let mut ranked = counts.into_iter().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    ranked
+}
+
+/// Reason why a ledger failed to load.
+fn failure(err: Error) -> String {
+    match err {
+        Error::IoError(err) => err.to_string(),
+        err => err.to_string(),
+    }
+}
+
+fn diagnostic(range: Range, severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        message,
+        ..Diagnostic::default()
+    }
+}
+
+impl Data {
+    /// Add `uri` as a root ledger and read it and all ledgers it includes.
+    pub fn load(&mut self, uri: &Url) -> Result<(), Error> {
+        self.read(uri)?;
+        self.roots.insert(uri.clone());
+        Ok(())
+    }
+
+    /// Returns `true` if `uri` has been read into this index.
+    pub fn contains(&self, uri: &Url) -> bool {
+        self.files.contains_key(uri)
+    }
+
+    /// Glob patterns of the files to watch for changes, i.e. the root ledgers and the paths of
+    /// all include directives.
+    pub fn watched(&self) -> BTreeSet<String> {
+        let escape = |path: &Path| path.to_str().map(glob::Pattern::escape);
+
+        self.roots
+            .iter()
+            .filter_map(|uri| escape(&uri.to_file_path().ok()?))
+            .chain(
+                self.files
+                    .values()
+                    .flat_map(|ledger| ledger.includes.iter())
+                    .filter_map(|include| match &include.directive.pattern {
+                        Some(pattern) => Some(pattern.clone()),
+                        None => escape(&include.directive.path),
+                    }),
+            )
+            .collect()
+    }
+
+    /// Recursively read ledgers, i.e. those included, unless already read.
+    fn read(&mut self, uri: &Url) -> Result<(), Error> {
+        if self.files.contains_key(uri) {
+            return Ok(());
+        }
+
+        let ledger = Ledger::read(uri)?;
+        let includes = ledger.included().cloned().collect::<Vec<_>>();
+        self.files.insert(uri.clone(), ledger);
+        self.failures.remove(uri);
+
+        // Descend into included ledgers, remember all that fail to load.
+        for include in includes {
+            if let Err(err) = self.read(&include) {
+                self.failures.insert(include, failure(err));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Re-read `uri` after it has been changed, created or deleted on disk and read ledgers it
+    /// newly includes. Ledgers including `uri` by a glob pattern are re-read as well. Ledgers
+    /// that are no longer included are dropped. Returns `false` if `uri` is not part of the
+    /// index.
+    pub fn reload(&mut self, uri: &Url) -> Result<bool, Error> {
+        let uri = &normalize_url(uri);
+        let file_path = uri.to_file_path().map_err(|_| Error::UriToPathConversion)?;
+
+        let globbed_by = self
+            .files
+            .iter()
+            .filter(|(_, ledger)| ledger.globs(&file_path))
+            .map(|(uri, _)| uri.clone())
+            .collect::<Vec<_>>();
+
+        let affected = self.roots.contains(uri)
+            || self.files.contains_key(uri)
+            || !globbed_by.is_empty()
+            || self
+                .files
+                .values()
+                .any(|ledger| ledger.included().any(|included| included == uri));
+
+        if !affected {
+            return Ok(false);
+        }
+
+        for uri in globbed_by.iter().chain(std::iter::once(uri)) {
+            self.files.remove(uri);
+            self.failures.remove(uri);
+        }
+
+        let mut result = Ok(true);
+
+        for uri in globbed_by.iter().chain(std::iter::once(uri)) {
+            let included = self
+                .files
+                .values()
+                .any(|ledger| ledger.included().any(|included| included == uri));
+
+            if !included && !uri.to_file_path().is_ok_and(|path| path.exists()) {
+                continue;
+            }
+
+            // Ledgers that are still included but fail to load, e.g. because they were deleted,
+            // are reported on their include directives.
+            match self.read(uri) {
+                Err(err) if included => {
+                    self.failures.insert(uri.clone(), failure(err));
+                }
+                Err(err) => result = Err(err),
+                Ok(()) => {}
+            }
+        }
+
+        self.prune();
+        result
+    }
+
+    /// Drop all ledgers that are not reachable from a root ledger.
+    fn prune(&mut self) {
+        let mut reachable = HashSet::new();
+        let mut queue = self.roots.iter().cloned().collect::<Vec<_>>();
+
+        while let Some(uri) = queue.pop() {
+            if reachable.contains(&uri) {
+                continue;
+            }
+
+            if let Some(ledger) = self.files.get(&uri) {
+                queue.extend(ledger.included().cloned());
+            }
+
+            reachable.insert(uri);
+        }
+
+        self.files.retain(|uri, _| reachable.contains(uri));
+        self.failures.retain(|uri, _| reachable.contains(uri));
+    }
+
+    /// Depth-first search for include directives that close a cycle.
+    fn find_cycles<'a>(
+        &'a self,
+        uri: &'a Url,
+        stack: &mut Vec<&'a Url>,
+        visited: &mut HashSet<&'a Url>,
+        cycles: &mut Vec<(&'a Url, &'a Include, &'a Url)>,
+    ) {
+        let ledger = match self.files.get(uri) {
+            Some(ledger) => ledger,
+            None => return,
+        };
+
+        if !visited.insert(uri) {
+            return;
+        }
+
+        stack.push(uri);
+
+        for include in &ledger.includes {
+            for file in &include.files {
+                if stack.contains(&file) {
+                    cycles.push((uri, include, file));
+                } else {
+                    self.find_cycles(file, stack, visited, cycles);
+                }
+            }
+        }
+
+        stack.pop();
+    }
+
+    /// Diagnostics keyed by ledger: accounts not starting with a root account, posting
+    /// currencies not allowed by the account's `open` directive, unknown options and include
+    /// directives referring to missing, unreadable, unparseable or cyclically included ledgers.
+    pub fn diagnostics(&self) -> HashMap<Url, Vec<Diagnostic>> {
+        let mut diagnostics: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+
+        let accounts = self.accounts();
+        let mut root_accounts = HashMap::new();
+
+        for (uri, symbol) in self.symbols() {
+            if symbol.kind != SymbolKind::Account {
+                continue;
+            }
+
+            let roots = root_accounts
+                .entry(uri)
+                .or_insert_with(|| self.root_accounts(uri));
+            let root = symbol.name.split(':').next().unwrap_or("");
+
+            if !roots.contains(&root) {
+                diagnostics.entry(uri.clone()).or_default().push(diagnostic(
+                    symbol.range,
+                    DiagnosticSeverity::ERROR,
+                    format!(
+                        "Account \"{}\" does not start with a root account: {}",
+                        symbol.name,
+                        roots.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        for (uri, ledger) in &self.files {
+            for posting in ledger
+                .transactions
+                .iter()
+                .flat_map(|transaction| transaction.postings.iter())
+            {
+                let open = accounts
+                    .get(posting.account.as_str())
+                    .and_then(|account| account.open);
+
+                let (currency, open) = match (&posting.currency, open) {
+                    (Some(currency), Some(open)) => (currency, open),
+                    _ => continue,
+                };
+
+                if !open.currencies.is_empty() && !open.currencies.contains(currency) {
+                    diagnostics.entry(uri.clone()).or_default().push(diagnostic(
+                        posting.range,
+                        DiagnosticSeverity::WARNING,
+                        format!(
+                            "Currency \"{}\" is not allowed in \"{}\" ({})",
+                            currency,
+                            posting.account,
+                            open.currencies.join(", ")
+                        ),
+                    ));
+                }
+            }
+
+            for option in &ledger.options {
+                if OPTIONS.iter().all(|(name, _)| *name != option.key) {
+                    diagnostics.entry(uri.clone()).or_default().push(diagnostic(
+                        option.range,
+                        DiagnosticSeverity::ERROR,
+                        format!("Unknown option \"{}\"", option.key),
+                    ));
+                }
+            }
+
+            for include in &ledger.includes {
+                if include.files.is_empty() {
+                    diagnostics.entry(uri.clone()).or_default().push(diagnostic(
+                        include.directive.range,
+                        DiagnosticSeverity::ERROR,
+                        format!(
+                            "File glob \"{}\" does not match any files",
+                            include.directive.path.display()
+                        ),
+                    ));
+                }
+
+                for file in &include.files {
+                    let (severity, message) = if let Some(reason) = self.failures.get(file) {
+                        (
+                            DiagnosticSeverity::ERROR,
+                            format!("Cannot load \"{}\": {}", file.path(), reason),
+                        )
+                    } else if self.files.get(file).is_some_and(|file| file.has_errors) {
+                        (
+                            DiagnosticSeverity::WARNING,
+                            format!("\"{}\" contains syntax errors", file.path()),
+                        )
+                    } else {
+                        continue;
+                    };
+
+                    diagnostics.entry(uri.clone()).or_default().push(diagnostic(
+                        include.directive.range,
+                        severity,
+                        message,
+                    ));
+                }
+            }
+        }
+
+        let mut cycles = Vec::new();
+        let mut visited = HashSet::new();
+
+        for root in &self.roots {
+            self.find_cycles(root, &mut Vec::new(), &mut visited, &mut cycles);
+        }
+
+        for (uri, include, file) in cycles {
+            diagnostics.entry(uri.clone()).or_default().push(diagnostic(
+                include.directive.range,
+                DiagnosticSeverity::ERROR,
+                format!("Cyclic include of \"{}\"", file.path()),
+            ));
+        }
+
+        diagnostics
+    }
+
+    /// All symbols and the ledger they appear in.
+    pub fn symbols(&self) -> impl Iterator<Item = (&Url, &Symbol)> {
+        self.files
+            .iter()
+            .flat_map(|(uri, ledger)| ledger.symbols.iter().map(move |symbol| (uri, symbol)))
+    }
+
+    /// Names of all symbols of `kind`.
+    pub fn names(&self, kind: SymbolKind) -> HashSet<&str> {
+        self.symbols()
+            .filter(|(_, symbol)| symbol.kind == kind)
+            .map(|(_, symbol)| symbol.name.as_str())
+            .collect()
+    }
+
+    /// All accounts, either opened or used, with their `open` and `close` directives. If an
+    /// account is opened or closed more than once, the earliest directive wins.
+    pub fn accounts(&self) -> HashMap<&str, Account<'_>> {
+        let mut accounts = self
+            .names(SymbolKind::Account)
+            .into_iter()
+            .map(|name| (name, Account::default()))
+            .collect::<HashMap<_, _>>();
+
+        for ledger in self.files.values() {
+            for open in &ledger.opens {
+                let account = accounts.entry(open.account.as_str()).or_default();
+
+                if account.open.is_none_or(|other| open.date < other.date) {
+                    account.open = Some(open);
+                }
+            }
+
+            for close in &ledger.closes {
+                let account = accounts.entry(close.account.as_str()).or_default();
+
+                if account.close.is_none_or(|other| close.date < other.date) {
+                    account.close = Some(close);
+                }
+            }
+        }
+
+        accounts
+    }
+
+    /// Names of all symbols of `kind` with their number of occurrences, most frequent first.
+    /// Tags of `pushtag` and `poptag` directives count once for each transaction in their scope
+    /// instead.
+    pub fn ranked(&self, kind: SymbolKind) -> Vec<(&str, usize)> {
+        let pushed_tags = self
+            .transactions()
+            .filter(|_| kind == SymbolKind::Tag)
+            .flat_map(|transaction| transaction.pushed_tags.iter())
+            .map(String::as_str);
+
+        rank(
+            self.symbols()
+                .filter(|(_, symbol)| {
+                    symbol.kind == kind && !matches!(symbol.directive, "pushtag" | "poptag")
+                })
+                .map(|(_, symbol)| symbol.name.as_str())
+                .chain(pushed_tags),
+        )
+    }
+
+    /// All transactions.
+    fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.files
+            .values()
+            .flat_map(|ledger| ledger.transactions.iter())
+    }
+
+    /// Usage of all symbols of `kind` that are used at least once.
+    pub fn usage(&self, kind: SymbolKind) -> HashMap<&str, Usage<'_>> {
+        let mut usage = HashMap::<_, Usage>::new();
+
+        for (_, symbol) in self.symbols() {
+            if symbol.kind != kind || symbol.definition {
+                continue;
+            }
+
+            let entry = usage.entry(symbol.name.as_str()).or_default();
+            entry.count += 1;
+
+            if let Some(date) = symbol.date.as_deref() {
+                if entry.last.is_none_or(|last| last < date) {
+                    entry.last = Some(date);
+                }
+            }
+        }
+
+        usage
+    }
+
+    /// Balance of `account` per currency, summing the units of its postings. Missing amounts
+    /// are inferred from the other postings unless a posting of the transaction has a cost or
+    /// price. Returns `None` if the sum overflows.
+    pub fn balance(&self, account: &str) -> Option<BTreeMap<&str, Decimal>> {
+        let mut balance = BTreeMap::new();
+
+        for transaction in self.transactions() {
+            let elided = transaction
+                .postings
+                .iter()
+                .filter(|posting| posting.number.is_none())
+                .count();
+
+            let inferable =
+                elided == 1 && transaction.postings.iter().all(|posting| !posting.weighted);
+
+            for posting in &transaction.postings {
+                let amount = match (posting.number.as_deref(), posting.currency.as_deref()) {
+                    (Some(number), Some(currency)) => Decimal::parse(number).map(|n| (currency, n)),
+                    _ => None,
+                };
+
+                match amount {
+                    Some((currency, number)) if posting.account == account => {
+                        let total = balance.entry(currency).or_default();
+                        *total = total.checked_add(number)?;
+                    }
+                    Some((currency, number)) if inferable => {
+                        let elided_account = transaction
+                            .postings
+                            .iter()
+                            .find(|posting| posting.number.is_none())
+                            .map(|posting| posting.account.as_str());
+
+                        if elided_account == Some(account) {
+                            let total = balance.entry(currency).or_default();
+                            *total = total.checked_add(number.checked_neg()?)?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Some(balance)
+    }
+
+    /// Most recent `commodity` directive of `currency`.
+    pub fn commodity(&self, currency: &str) -> Option<&Commodity> {
+        self.files
+            .values()
+            .flat_map(|ledger| ledger.commodities.iter())
+            .filter(|commodity| commodity.currency == currency)
+            .max_by(|a, b| a.date.cmp(&b.date))
+    }
+
+    /// Ledgers reachable from `root` in depth-first include order, starting with `root`.
+    fn reachable<'a>(&'a self, root: &'a Url) -> Vec<&'a Url> {
+        let mut reachable = Vec::new();
+        let mut stack = vec![root];
+
+        while let Some(uri) = stack.pop() {
+            if reachable.contains(&uri) {
+                continue;
+            }
+
+            if let Some(ledger) = self.files.get(uri) {
+                let mut included = ledger.included().collect::<Vec<_>>();
+                included.reverse();
+                stack.extend(included);
+            }
+
+            reachable.push(uri);
+        }
+
+        reachable
+    }
+
+    /// Names of the root accounts of the ledger `uri`, possibly renamed by `name_*` options.
+    /// Options are looked up in the ledgers reachable from the root ledger including `uri`, the
+    /// first by URI if there are several, and the first one in include order wins.
+    pub fn root_accounts(&self, uri: &Url) -> Vec<&str> {
+        let mut roots = self.roots.iter().collect::<Vec<_>>();
+        roots.sort();
+
+        let ledgers = roots
+            .into_iter()
+            .map(|root| self.reachable(root))
+            .find(|reachable| reachable.contains(&uri))
+            .unwrap_or_default();
+
+        ROOT_ACCOUNTS
+            .iter()
+            .map(|(option, default)| {
+                ledgers
+                    .iter()
+                    .filter_map(|uri| self.files.get(*uri))
+                    .flat_map(|ledger| ledger.options.iter())
+                    .find(|other| other.key == *option)
+                    .map_or(*default, |other| other.value.as_str())
+            })
+            .collect()
+    }
+
+    /// Currencies of postings to `account` with their number of occurrences, most frequent
+    /// first.
+    pub fn posting_currencies(&self, account: &str) -> Vec<(&str, usize)> {
+        rank(
+            self.transactions()
+                .flat_map(|transaction| transaction.postings.iter())
+                .filter(|posting| posting.account == account)
+                .filter_map(|posting| posting.currency.as_deref()),
+        )
+    }
+
+    /// Most recent transaction with `payee` that has postings. Of transactions with the same
+    /// date, the last one of the last ledger by URI wins.
+    pub fn last_transaction(&self, payee: &str) -> Option<&Transaction> {
+        self.files
+            .iter()
+            .flat_map(|(uri, ledger)| {
+                ledger
+                    .transactions
+                    .iter()
+                    .map(move |transaction| (uri, transaction))
+            })
+            .filter(|(_, transaction)| {
+                transaction.payee.as_deref() == Some(payee) && !transaction.postings.is_empty()
+            })
+            .max_by(|(a_uri, a), (b_uri, b)| {
+                (&a.date, a_uri.as_str()).cmp(&(&b.date, b_uri.as_str()))
+            })
+            .map(|(_, transaction)| transaction)
+    }
+
+    /// Narrations with their number of occurrences. Narrations used with `payee` come first,
+    /// each group most frequent first.
+    pub fn narrations(&self, payee: &str) -> Vec<(&str, usize)> {
+        let mut ranked = rank(
+            self.transactions()
+                .filter(|transaction| transaction.payee.as_deref() == Some(payee))
+                .filter_map(|transaction| transaction.narration.as_deref()),
+        );
+
+        let seen = ranked.iter().map(|(name, _)| *name).collect::<HashSet<_>>();
+
+        let others = rank(
+            self.transactions()
+                .filter_map(|transaction| transaction.narration.as_deref())
+                .filter(|narration| !seen.contains(narration)),
+        );
+
+        ranked.extend(others);
+        ranked
+    }
+
+    /// All metadata entries.
+    fn metadata(&self) -> impl Iterator<Item = &Metadata> {
+        self.files
+            .values()
+            .flat_map(|ledger| ledger.metadata.iter())
+    }
+
+    /// Metadata keys used with `directive` and their number of occurrences, most frequent first.
+    pub fn metadata_keys(&self, directive: &str) -> Vec<(&str, usize)> {
+        rank(
+            self.metadata()
+                .filter(|metadata| metadata.directive == directive)
+                .map(|metadata| metadata.key.as_str()),
+        )
+    }
+
+    /// Values of metadata `key` used with `directive` and their number of occurrences, most
+    /// frequent first.
+    pub fn metadata_values(&self, directive: &str, key: &str) -> Vec<(&str, usize)> {
+        rank(
+            self.metadata()
+                .filter(|metadata| metadata.directive == directive && metadata.key == key)
+                .map(|metadata| metadata.value.as_str()),
+        )
+    }
+
+    /// Locations of all occurrences of a symbol including its definitions, ordered by file and
+    /// position.
+    pub fn occurrences(&self, kind: SymbolKind, name: &str) -> Vec<Location> {
+        self.locations(|symbol| symbol.kind == kind && symbol.name == name)
+    }
+
+    /// Location of the earliest use of a symbol by the date of its directive. Ties and uses
+    /// without date are ordered by file and position.
+    pub fn first_use(&self, kind: SymbolKind, name: &str) -> Option<Location> {
+        self.symbols()
+            .filter(|(_, symbol)| symbol.kind == kind && symbol.name == name)
+            .min_by_key(|&(uri, symbol)| {
+                (
+                    symbol.date.is_none(),
+                    symbol.date.as_deref(),
+                    uri.as_str(),
+                    symbol.range.start.line,
+                    symbol.range.start.character,
+                )
+            })
+            .map(|(uri, symbol)| Location {
+                uri: uri.clone(),
+                range: symbol.range,
+            })
+    }
+
+    /// Locations of all references to a symbol, ordered by file and position. Optionally the
+    /// references include its definitions and, for accounts, those of its sub-accounts.
+    pub fn references(
+        &self,
+        kind: SymbolKind,
+        name: &str,
+        include_declaration: bool,
+        include_subaccounts: bool,
+    ) -> Vec<Location> {
+        let parent = format!("{}:", name);
+
+        self.locations(|symbol| {
+            symbol.kind == kind
+                && (symbol.name == name
+                    || (include_subaccounts
+                        && kind == SymbolKind::Account
+                        && symbol.name.starts_with(&parent)))
+                && (include_declaration || !symbol.definition)
+        })
+    }
+
+    /// Edits renaming all occurrences of an account, commodity or payee to `new_name`,
+    /// optionally including the sub-accounts of an account, as requested in the ledger `uri`
+    /// whose root accounts a new account name must start with. Symbols in `documents` replace
+    /// the indexed ones of these files, so that edits of open documents match their unsaved
+    /// text. Fails if the new name is invalid or if a renamed account or commodity would
+    /// collide with an existing one. Payees are merged instead.
+    pub fn rename(
+        &self,
+        uri: &Url,
+        kind: SymbolKind,
+        name: &str,
+        new_name: &str,
+        include_subaccounts: bool,
+        documents: &HashMap<Url, Vec<Symbol>>,
+    ) -> Result<HashMap<Url, Vec<TextEdit>>, Error> {
+        let roots = self.root_accounts(uri);
+        let mut segments = new_name.split(':');
+
+        let valid = match kind {
+            SymbolKind::Account => {
+                segments.next().is_some_and(|root| roots.contains(&root))
+                    && new_name.contains(':')
+                    && segments.all(|segment| {
+                        segment.starts_with(|c: char| c.is_uppercase() || c.is_ascii_digit())
+                            && segment.chars().all(|c| c.is_alphanumeric() || c == '-')
+                    })
+            }
+            SymbolKind::Commodity => {
+                new_name.len() <= 24
+                    && new_name.starts_with(|c: char| c.is_ascii_uppercase())
+                    && new_name.ends_with(|c: char| c.is_ascii_uppercase() || c.is_ascii_digit())
+                    && new_name
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "'._-".contains(c))
+            }
+            SymbolKind::Payee => !new_name.is_empty() && !new_name.contains('"'),
+            _ => false,
+        };
+
+        if !valid {
+            return Err(Error::InvalidName(new_name.to_string()));
+        }
+
+        let parent = format!("{}:", name);
+
+        let renamed = |other: &str| -> Option<String> {
+            if other == name {
+                Some(new_name.to_string())
+            } else if include_subaccounts
+                && kind == SymbolKind::Account
+                && other.starts_with(&parent)
+            {
+                Some(format!("{}{}", new_name, &other[name.len()..]))
             } else {
-                "".to_string()
+                None
+            }
+        };
+
+        let symbols = self
+            .symbols()
+            .filter(|(uri, _)| !documents.contains_key(*uri))
+            .chain(
+                documents
+                    .iter()
+                    .flat_map(|(uri, symbols)| symbols.iter().map(move |symbol| (uri, symbol))),
+            )
+            .filter(|(_, symbol)| symbol.kind == kind)
+            .collect::<Vec<_>>();
+
+        let names = symbols
+            .iter()
+            .map(|(_, symbol)| symbol.name.as_str())
+            .collect::<HashSet<_>>();
+
+        for other in names.iter().filter(|_| kind != SymbolKind::Payee) {
+            if let Some(other) = renamed(other) {
+                if names.contains(other.as_str()) && renamed(&other).is_none() {
+                    return Err(Error::AlreadyExists(other));
+                }
             }
         }
-        "option" => {
-            let key = node_text_by_field_name(&node, "key", bytes)?;
-            let value = node_text_by_field_name(&node, "value", bytes)?;
 
-            if cursor.goto_next_sibling() {
-                format!(
-                    "option {} {}{}{}",
-                    key,
-                    value,
-                    newlines(cursor),
-                    reformat_top_level(cursor, text)?
-                )
-            } else {
-                format!("option {} {}", key, value)
-            }
-        }
-        "plugin" => {
-            let plugin = node.child(1).unwrap().utf8_text(bytes)?;
+        let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+
+        for (uri, symbol) in symbols {
+            if let Some(new_text) = renamed(&symbol.name) {
+                edits.entry(uri.clone()).or_default().push(TextEdit {
+                    range: symbol.range,
+                    new_text: match kind {
+                        SymbolKind::Payee => format!("\"{}\"", new_text),
+                        _ => new_text,
+                    },
+                });
+            }
+        }
+
+        Ok(edits)
+    }
+
+    /// Locations of all definitions of a symbol, ordered by file and position.
+    pub fn definitions(&self, kind: SymbolKind, name: &str) -> Vec<Location> {
+        self.locations(|symbol| symbol.kind == kind && symbol.name == name && symbol.definition)
+    }
+
+    fn locations<F: Fn(&Symbol) -> bool>(&self, predicate: F) -> Vec<Location> {
+        let mut locations = self
+            .symbols()
+            .filter(|(_, symbol)| predicate(symbol))
+            .map(|(uri, symbol)| Location {
+                uri: uri.clone(),
+                range: symbol.range,
+            })
+            .collect::<Vec<_>>();
+
+        locations.sort_by(|a, b| {
+            (a.uri.as_str(), a.range.start.line, a.range.start.character).cmp(&(
+                b.uri.as_str(),
+                b.range.start.line,
+                b.range.start.character,
+            ))
+        });
+
+        locations
+    }
+}
+
+fn reformat_postings(postings: &Node, text: &str) -> Result<String, Error> {
+    let mut cursor = postings.walk();
+    let bytes = text.as_bytes();
+
+    let comments = postings
+        .children(&mut cursor)
+        .filter(|p| p.kind() == "comment")
+        .map(|p| Ok::<_, Error>(format!("  {}\n", p.utf8_text(bytes)?)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let postings = postings
+        .children(&mut cursor)
+        .filter(|p| p.kind() == "posting")
+        .collect::<Vec<_>>();
+
+    let formatted = postings
+        .into_iter()
+        .map(|p| {
+            let account = p
+                .child_by_field_name("account")
+                .ok_or_else(|| Error::InvalidState)?
+                .utf8_text(bytes)?;
+
+            let amount = {
+                match p.child_by_field_name("amount") {
+                    Some(amount) => {
+                        let mut amount_children = amount.children(&mut cursor);
+
+                        assert_eq!(amount_children.len(), 2);
+
+                        let number = amount_children
+                            .next()
+                            .ok_or_else(|| Error::InvalidState)?
+                            .utf8_text(bytes)?;
+
+                        let width = 50 - 4 - account.len();
+
+                        let currency = amount_children
+                            .next()
+                            .ok_or_else(|| Error::InvalidState)?
+                            .utf8_text(bytes)?;
+
+                        // We want to align so that the number period is always at column position 50. Hence we
+                        // have to pad with 50 - 2 spaces before account - 1 space after account - 1 period -
+                        // length of account.
+                        match number.find('.') {
+                            Some(position) => {
+                                let numerator = &number[..position];
+                                let denominator = &number[position + 1..];
+                                format!(
+                                    " {:>width$}.{} {}",
+                                    numerator,
+                                    denominator,
+                                    currency,
+                                    width = width
+                                )
+                            }
+                            None => format!(" {:>width$} {}", number, currency, width = width),
+                        }
+                    }
+                    None => "".to_string(),
+                }
+            };
+
+            let cost = match p.child_by_field_name("cost_spec") {
+                Some(cost) => format!(" {}", cost.utf8_text(bytes)?),
+                None => "".to_string(),
+            };
+
+            let comment = match p.child_by_field_name("comment") {
+                Some(comment) => format!("  {}", comment.utf8_text(bytes)?),
+                None => "".to_string(),
+            };
+
+            Ok::<_, Error>(format!("  {}{}{}{}", account, amount, cost, comment))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(format!("{}{}", comments.join("\n"), formatted.join("\n")))
+}
+
+fn reformat_top_level(cursor: &mut TreeCursor, text: &str) -> Result<String, Error> {
+    let node = cursor.node();
+    let bytes = text.as_bytes();
+    let end_point = node.range().end_point;
+
+    let newlines = |cursor: &TreeCursor| -> String {
+        let current = cursor.node().range();
+        "\n".repeat(current.start_point.row - end_point.row + 1)
+    };
+
+    let formatted = match node.kind() {
+        "file" => {
+            if cursor.goto_first_child() {
+                reformat_top_level(cursor, text)?
+            } else {
+                "".to_string()
+            }
+        }
+        "option" => {
+            let key = node_text_by_field_name(&node, "key", bytes)?;
+            let value = node_text_by_field_name(&node, "value", bytes)?;
+
+            if cursor.goto_next_sibling() {
+                format!(
+                    "option {} {}{}{}",
+                    key,
+                    value,
+                    newlines(cursor),
+                    reformat_top_level(cursor, text)?
+                )
+            } else {
+                format!("option {} {}", key, value)
+            }
+        }
+        "plugin" => {
+            let plugin = node.child(1).unwrap().utf8_text(bytes)?;
+
+            if cursor.goto_next_sibling() {
+                format!(
+                    "plugin {}{}{}",
+                    plugin,
+                    newlines(cursor),
+                    reformat_top_level(cursor, text)?
+                )
+            } else {
+                format!("plugin {}", plugin)
+            }
+        }
+        "open" => {
+            let date = node_text_by_field_name(&node, "date", bytes)?;
+            let account = node_text_by_field_name(&node, "account", bytes)?;
+
+            if cursor.goto_next_sibling() {
+                format!(
+                    "{} open {}{}{}",
+                    date,
+                    account,
+                    newlines(cursor),
+                    reformat_top_level(cursor, text)?
+                )
+            } else {
+                format!("{} open {}", date, account)
+            }
+        }
+        "include" => {
+            let include = node.child(1).unwrap().utf8_text(bytes)?;
+
+            if cursor.goto_next_sibling() {
+                format!(
+                    "include {}{}{}",
+                    include,
+                    newlines(cursor),
+                    reformat_top_level(cursor, text)?
+                )
+            } else {
+                format!("include {}", include)
+            }
+        }
+        "transaction" => {
+            let date = node_text_by_field_name(&node, "date", bytes)?;
+            let txn = node_text_by_field_name(&node, "txn", bytes)?;
+
+            let txn_strings = node
+                .child_by_field_name("txn_strings")
+                .ok_or_else(|| Error::InvalidState)?
+                .children(&mut node.walk())
+                .collect::<Vec<_>>();
+
+            let payee = txn_strings[0].utf8_text(bytes)?;
+
+            let first_line = match txn_strings.len() {
+                1 => {
+                    format!("{} {} {}", date, txn, payee)
+                }
+                2 => {
+                    let narration = txn_strings[1].utf8_text(bytes)?;
+                    format!("{} {} {} {}", date, txn, payee, narration)
+                }
+                _ => return Err(Error::UnexpectedFormat),
+            };
+
+            let posting = node
+                .child_by_field_name("posting_or_kv_list")
+                .ok_or_else(|| Error::InvalidState)?;
+
+            if cursor.goto_next_sibling() {
+                format!(
+                    "{}\n{}{}{}",
+                    first_line,
+                    reformat_postings(&posting, text)?,
+                    newlines(cursor),
+                    reformat_top_level(cursor, text)?
+                )
+            } else {
+                format!("{}\n{}", first_line, reformat_postings(&posting, text)?)
+            }
+        }
+        "\n" => {
+            if cursor.goto_next_sibling() {
+                format!("\n{}", reformat_top_level(cursor, text)?)
+            } else {
+                "".to_string()
+            }
+        }
+        _ => "".to_string(),
+    };
+
+    Ok(formatted)
+}
+
+/// Reformat a document from its text and the tree parsed from it.
+pub fn reformat(text: &str, tree: &Tree) -> Result<Option<String>, Error> {
+    let mut cursor = tree.root_node().walk();
+
+    Ok(Some(reformat_top_level(&mut cursor, text)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+    use tower_lsp::lsp_types::Url;
+
+    fn url_from_file_path<P: AsRef<Path>>(path: P) -> Result<Url, Error> {
+        Ok(Url::from_file_path(path).map_err(|_| Error::UriToPathConversion)?)
+    }
+
+    fn read<P: AsRef<Path>>(path: P) -> Result<Data, Error> {
+        let mut data = Data::default();
+        data.load(&url_from_file_path(path)?)?;
+        Ok(data)
+    }
+
+    fn reformat<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+        let text = read_to_string(path)?;
+        let tree = super::parse(&text, None)?;
+        Ok(super::reformat(&text, &tree)?.ok_or_else(|| Error::UnexpectedFormat)?)
+    }
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-07-10 "foo" "bar"
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR
+        "#
+        )?;
+
+        let data = read(file.path())?;
+
+        let accounts = data.names(SymbolKind::Account);
+        assert_eq!(accounts.len(), 2);
+
+        assert!(accounts.contains("Expenses:Cash"));
+        assert!(accounts.contains("Assets:Checking"));
+
+        let currencies = data.names(SymbolKind::Commodity);
+        assert_eq!(currencies.len(), 1);
+        assert!(currencies.contains("EUR"));
+
+        let payees = data.names(SymbolKind::Payee);
+        assert_eq!(payees.len(), 1);
+        assert!(payees.contains("foo"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn commodity_definition() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 commodity USD
+  name: "US Dollar"
+  type: "Currency"
+2015-01-01 commodity EUR
+  name: "Euro"
+  type: "Currency""#
+        )?;
+
+        let data = read(file.path())?;
+
+        assert_eq!(data.names(SymbolKind::Commodity).len(), 2);
+
+        let usd_locations = data.definitions(SymbolKind::Commodity, "USD");
+        assert_eq!(usd_locations.len(), 1);
+        assert_eq!(usd_locations[0].range.start.line, 0);
+
+        let eur_locations = data.definitions(SymbolKind::Commodity, "EUR");
+        assert_eq!(eur_locations.len(), 1);
+        assert_eq!(eur_locations[0].range.start.line, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn symbols() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let transactions_file_path = dir.path().join("transactions.beancount");
+        let mut transactions_file = File::create(&transactions_file_path)?;
+
+        write!(
+            transactions_file,
+            r#"2021-07-10 * "foo" "bar" #trip ^invoice-1
+  receipt: "foo.pdf"
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR
+"#
+        )?;
+
+        let main_file_path = dir.path().join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
+
+        write!(
+            main_file,
+            r#"include "transactions.beancount"
+
+2015-01-01 open Assets:Checking EUR
+2015-01-01 commodity EUR
+  name: "Euro"
+"#
+        )?;
+
+        let data = read(&main_file_path)?;
+
+        assert!(data.names(SymbolKind::Payee).contains("foo"));
+        assert!(data.names(SymbolKind::Tag).contains("trip"));
+        assert!(data.names(SymbolKind::Link).contains("invoice-1"));
+
+        let keys = data.names(SymbolKind::MetadataKey);
+        assert!(keys.contains("name"));
+        assert!(keys.contains("receipt"));
+
+        let definitions = data.definitions(SymbolKind::Account, "Assets:Checking");
+        assert_eq!(definitions.len(), 1);
+        assert_eq!(definitions[0].uri, url_from_file_path(&main_file_path)?);
+        assert_eq!(definitions[0].range.start.line, 2);
+
+        let occurrences = data.occurrences(SymbolKind::Account, "Assets:Checking");
+        assert_eq!(occurrences.len(), 2);
+        assert_eq!(data.occurrences(SymbolKind::Commodity, "EUR").len(), 4);
+
+        let (uri, tag) = data
+            .symbols()
+            .find(|(_, symbol)| symbol.kind == SymbolKind::Tag)
+            .unwrap();
+
+        assert_eq!(uri, &url_from_file_path(&transactions_file_path)?);
+        assert_eq!(tag.directive, "transaction");
+        assert_eq!(tag.date.as_deref(), Some("2021-07-10"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pushed_tags() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"pushtag #berlin
+
+2021-07-10 * "foo" "bar" #trip
+  Expenses:Cash       100.00 EUR
+  Assets:Checking
+
+2021-07-11 * "foo" "bar" #trip #berlin
+  Expenses:Cash       100.00 EUR
+  Assets:Checking
+
+poptag #berlin
+
+2021-07-12 * "foo" "bar" #trip
+  Expenses:Cash       100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let data = read(file.path())?;
+
+        let pushed_tags = data
+            .transactions()
+            .map(|transaction| transaction.pushed_tags.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            pushed_tags,
+            vec![vec!["berlin".to_string()], Vec::new(), Vec::new()]
+        );
+
+        assert_eq!(
+            data.ranked(SymbolKind::Tag),
+            vec![("trip", 3), ("berlin", 2)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking EUR
+  category: "bank"
+2021-07-10 * "foo" "bar"
+  category: "food"
+  Expenses:Food       100.00 EUR
+    receipt: "foo.pdf"
+  Assets:Checking    -100.00 EUR
+2021-07-11 * "foo" "bar"
+  category: "food"
+  Expenses:Food       100.00 EUR
+  Assets:Checking    -100.00 EUR
+2021-07-12 * "baz" "qux"
+  category: "rent"
+  Expenses:Rent       100.00 EUR
+  Assets:Checking    -100.00 EUR
+"#
+        )?;
+
+        let data = read(file.path())?;
+
+        assert_eq!(
+            data.metadata_keys("transaction"),
+            vec![("category", 3), ("receipt", 1)]
+        );
+        assert_eq!(data.metadata_keys("open"), vec![("category", 1)]);
+
+        assert_eq!(
+            data.metadata_values("transaction", "category"),
+            vec![("\"food\"", 2), ("\"rent\"", 1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn narrations() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-01-01 * "Landlord" "Rent January"
+  Expenses:Rent       500.00 EUR
+  Assets:Checking    -500.00 EUR
+2021-01-02 * "Bakery" "Bread"
+  Expenses:Food         2.00 EUR
+  Assets:Checking      -2.00 EUR
+2021-01-03 * "Bread"
+  Expenses:Food         2.00 EUR
+  Assets:Checking      -2.00 EUR
+2021-02-01 * "Landlord" "Rent February"
+  Expenses:Rent       500.00 EUR
+  Assets:Checking    -500.00 EUR
+"#
+        )?;
+
+        let data = read(file.path())?;
+
+        assert_eq!(
+            data.narrations("Landlord"),
+            vec![("Rent February", 1), ("Rent January", 1), ("Bread", 2)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn last_transaction() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-02-01 * "Landlord" "Rent February" #home
+  Expenses:Rent       510.00 EUR
+  Assets:Checking
+2021-01-01 * "Landlord" "Rent January"
+  Expenses:Rent       500.00 EUR
+  Assets:Checking    -500.00 EUR
+2021-02-01 * "Landlord" "Rent February, corrected"
+  Expenses:Rent       520.00 EUR
+  Assets:Checking
+2021-03-01 * "Landlord" "Rent March"
+"#
+        )?;
+
+        let data = read(file.path())?;
+        let transaction = data.last_transaction("Landlord").unwrap();
+
+        assert_eq!(transaction.date, "2021-02-01");
+        assert_eq!(
+            transaction.narration.as_deref(),
+            Some("Rent February, corrected")
+        );
+        assert_eq!(transaction.postings.len(), 2);
+        assert_eq!(transaction.postings[0].account, "Expenses:Rent");
+        assert_eq!(transaction.postings[0].number.as_deref(), Some("520.00"));
+        assert_eq!(transaction.postings[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(transaction.postings[1].number, None);
+
+        assert!(data.last_transaction("Bakery").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn usage() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking
+2015-01-01 open Expenses:Rent
+2021-02-01 * "Landlord" "Rent February"
+  Expenses:Rent       510.00 EUR
+  Assets:Checking
+2021-01-01 * "Landlord" "Rent January"
+  Expenses:Rent       500.00 EUR
+  Assets:Checking    -500.00 EUR
+2021-01-15 balance Assets:Checking  1000.00 EUR
+"#
+        )?;
+
+        let data = read(file.path())?;
+        let usage = data.usage(SymbolKind::Account);
+
+        assert_eq!(usage["Expenses:Rent"].count, 2);
+        assert_eq!(usage["Expenses:Rent"].last, Some("2021-02-01"));
+        assert_eq!(usage["Assets:Checking"].count, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn first_use() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-07-10 * "foo" "bar"
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+
+2021-07-01 * "foo" "bar"
+  Expenses:Food        10.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let data = read(file.path())?;
+        let location = data
+            .first_use(SymbolKind::Account, "Expenses:Food")
+            .unwrap();
+
+        assert_eq!(location.range.start.line, 5);
+        assert!(data
+            .first_use(SymbolKind::Account, "Expenses:Rent")
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn references() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Expenses:Food
+2015-01-01 open Expenses:Food:Groceries
+2015-01-01 open Expenses:FoodTruck
+
+2021-07-10 * "foo" "bar"
+  Expenses:Food:Groceries  100.00 EUR
+  Expenses:FoodTruck        10.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let data = read(file.path())?;
+        let lines = |locations: Vec<Location>| {
+            locations
+                .iter()
+                .map(|location| location.range.start.line)
+                .collect::<Vec<_>>()
+        };
+
+        let references = |declaration, subaccounts| {
+            lines(data.references(
+                SymbolKind::Account,
+                "Expenses:Food",
+                declaration,
+                subaccounts,
+            ))
+        };
+
+        assert_eq!(references(true, false), vec![0]);
+        assert!(references(false, false).is_empty());
+        assert_eq!(references(true, true), vec![0, 1, 5]);
+        assert_eq!(references(false, true), vec![5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Expenses:Food
+2015-01-01 open Expenses:Food:Groceries
+2015-01-01 open Expenses:Meals:Restaurant
+
+2021-07-10 * "foo" "bar"
+  Expenses:Food:Groceries  100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let data = read(file.path())?;
+        let uri = url_from_file_path(file.path())?;
+
+        let edits = data.rename(
+            &uri,
+            SymbolKind::Account,
+            "Expenses:Food",
+            "Expenses:Meals",
+            false,
+            &HashMap::new(),
+        )?;
+        let mut edits = edits[&uri].clone();
+        edits.sort_by_key(|edit| edit.range.start.line);
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, "Expenses:Meals");
+
+        let edits = data.rename(
+            &uri,
+            SymbolKind::Account,
+            "Expenses:Food",
+            "Expenses:Meals",
+            true,
+            &HashMap::new(),
+        )?;
+        let mut edits = edits[&uri].clone();
+        edits.sort_by_key(|edit| edit.range.start.line);
+        assert_eq!(edits.len(), 3);
+        assert_eq!(edits[1].new_text, "Expenses:Meals:Groceries");
+        assert_eq!(edits[2].range.start.line, 5);
+
+        assert!(matches!(
+            data.rename(
+                &uri,
+                SymbolKind::Account,
+                "Expenses:Food:Groceries",
+                "Expenses:Meals:Restaurant",
+                false,
+                &HashMap::new()
+            ),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        assert!(matches!(
+            data.rename(
+                &uri,
+                SymbolKind::Account,
+                "Expenses:Food",
+                "Revenue:Food",
+                false,
+                &HashMap::new()
+            ),
+            Err(Error::InvalidName(_))
+        ));
+
+        let edits = data.rename(
+            &uri,
+            SymbolKind::Account,
+            "Expenses:Food",
+            "Expenses:Übrige",
+            false,
+            &HashMap::new(),
+        )?;
+        assert_eq!(edits[&uri][0].new_text, "Expenses:Übrige");
+
+        assert!(matches!(
+            data.rename(
+                &uri,
+                SymbolKind::Account,
+                "Expenses:Food",
+                "Expenses:übrige",
+                false,
+                &HashMap::new()
+            ),
+            Err(Error::InvalidName(_))
+        ));
+
+        let text = format!(
+            "2015-01-01 open Assets:Checking\n{}",
+            read_to_string(file.path())?
+        );
+        let tree = super::parse(&text, None)?;
+        let documents = HashMap::from([(uri.clone(), super::symbols(&tree, &text))]);
+
+        let edits = data.rename(
+            &uri,
+            SymbolKind::Account,
+            "Expenses:Food",
+            "Expenses:Meals",
+            true,
+            &documents,
+        )?;
+        let mut lines = edits[&uri]
+            .iter()
+            .map(|edit| edit.range.start.line)
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        assert_eq!(lines, vec![1, 2, 6]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_commodity_and_payee() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 commodity EUR
+2015-01-01 commodity USD
+2015-01-01 open Assets:Checking EUR,USD
+
+2021-07-10 * "foo" "bar"
+  Assets:Broker            10 EUR {{1.10 USD}}
+  Assets:Checking      -11.00 USD @ 1 EUR
+
+2021-07-11 * "foobar" "bar"
+  Expenses:Food        100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let data = read(file.path())?;
+        let uri = url_from_file_path(file.path())?;
+
+        let edits = data.rename(
+            &uri,
+            SymbolKind::Commodity,
+            "EUR",
+            "EURO",
+            false,
+            &HashMap::new(),
+        )?;
+        let mut lines = edits[&uri]
+            .iter()
+            .map(|edit| edit.range.start.line)
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        assert_eq!(lines, vec![0, 2, 5, 6, 9]);
+
+        assert!(matches!(
+            data.rename(
+                &uri,
+                SymbolKind::Commodity,
+                "EUR",
+                "USD",
+                false,
+                &HashMap::new()
+            ),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        assert!(matches!(
+            data.rename(
+                &uri,
+                SymbolKind::Commodity,
+                "EUR",
+                "euro",
+                false,
+                &HashMap::new()
+            ),
+            Err(Error::InvalidName(_))
+        ));
+
+        let edits = data.rename(
+            &uri,
+            SymbolKind::Payee,
+            "foo",
+            "Foo Inc.",
+            false,
+            &HashMap::new(),
+        )?;
+        assert_eq!(edits[&uri].len(), 1);
+        assert_eq!(edits[&uri][0].range.start.line, 4);
+        assert_eq!(edits[&uri][0].new_text, "\"Foo Inc.\"");
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_close() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking EUR,USD "STRICT"
+2015-01-01 open Assets:Savings
+2020-12-31 close Assets:Savings
+
+2021-07-10 * "foo" "bar"
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR
+"#
+        )?;
+
+        let data = read(file.path())?;
+        let accounts = data.accounts();
+        assert_eq!(accounts.len(), 3);
+
+        let checking = &accounts["Assets:Checking"];
+        let open = checking.open.unwrap();
+        assert_eq!(open.date, "2015-01-01");
+        assert_eq!(open.currencies, vec!["EUR", "USD"]);
+        assert_eq!(open.booking.as_deref(), Some("STRICT"));
+        assert!(checking.close.is_none());
+
+        let savings = &accounts["Assets:Savings"];
+        assert!(savings.open.unwrap().currencies.is_empty());
+        assert_eq!(savings.close.unwrap().date, "2020-12-31");
+
+        let cash = &accounts["Expenses:Cash"];
+        assert!(cash.open.is_none());
+        assert!(cash.close.is_none());
+
+        assert!(!checking.is_open("2014-12-31"));
+        assert!(checking.is_open("2015-01-01"));
+        assert!(savings.is_open("2020-12-31"));
+        assert!(!savings.is_open("2021-07-10"));
+        assert!(cash.is_open("2021-07-10"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn include() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let commodity_file_path = dir.path().join("commodities.beancount");
+        let mut commodity_file = File::create(&commodity_file_path)?;
+
+        write!(
+            commodity_file,
+            r#"2015-01-01 commodity USD
+  name: "US Dollar"
+  type: "Currency""#
+        )?;
+
+        let main_file_path = dir.path().join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
+
+        write!(
+            main_file,
+            r#"include "commodities.beancount"
+
+2021-07-10 * "foo" "bar"
+  Expenses:Cash       100.00 USD
+  Assets:Checking    -100.00 USD
+        "#
+        )?;
+
+        let data = read(&main_file_path)?;
+        let usd_locations = data.definitions(SymbolKind::Commodity, "USD");
+        assert_eq!(usd_locations[0].uri.path(), commodity_file_path.as_os_str());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let commodity_file_path = dir.path().join("commodities.beancount");
+        let mut commodity_file = File::create(&commodity_file_path)?;
+        writeln!(commodity_file, "2015-01-01 commodity USD")?;
+
+        let main_file_path = dir.path().join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
+        write!(main_file, r#"include "commodities.beancount""#)?;
+
+        let mut data = read(&main_file_path)?;
+        assert!(data.names(SymbolKind::Commodity).contains("USD"));
+
+        let mut commodity_file = File::create(&commodity_file_path)?;
+        writeln!(commodity_file, "2015-01-01 commodity EUR")?;
+
+        assert!(data.reload(&url_from_file_path(&commodity_file_path)?)?);
+        assert!(!data.names(SymbolKind::Commodity).contains("USD"));
+        assert!(data.names(SymbolKind::Commodity).contains("EUR"));
+
+        File::create(&main_file_path)?;
+
+        assert!(data.reload(&url_from_file_path(&main_file_path)?)?);
+        assert!(data.names(SymbolKind::Commodity).is_empty());
+        assert!(!data.contains(&url_from_file_path(&commodity_file_path)?));
+
+        let unrelated_file_path = dir.path().join("unrelated.beancount");
+        assert!(!data.reload(&url_from_file_path(&unrelated_file_path)?)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn include_parent_directory() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
 
-            if cursor.goto_next_sibling() {
-                format!(
-                    "plugin {}{}{}",
-                    plugin,
-                    newlines(cursor),
-                    reformat_top_level(cursor, text)?
-                )
-            } else {
-                format!("plugin {}", plugin)
-            }
-        }
-        "open" => {
-            let date = node_text_by_field_name(&node, "date", bytes)?;
-            let account = node_text_by_field_name(&node, "account", bytes)?;
+        let accounts_file_path = dir.path().join("accounts.beancount");
+        let mut accounts_file = File::create(&accounts_file_path)?;
+        writeln!(accounts_file, "2015-01-01 open Assets:Checking")?;
 
-            if cursor.goto_next_sibling() {
-                format!(
-                    "{} open {}{}{}",
-                    date,
-                    account,
-                    newlines(cursor),
-                    reformat_top_level(cursor, text)?
-                )
-            } else {
-                format!("{} open {}", date, account)
-            }
-        }
-        "include" => {
-            let include = node.child(1).unwrap().utf8_text(bytes)?;
+        let main_file_path = dir.path().join("2024").join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
+        write!(main_file, r#"include "../accounts.beancount""#)?;
 
-            if cursor.goto_next_sibling() {
-                format!(
-                    "include {}{}{}",
-                    include,
-                    newlines(cursor),
-                    reformat_top_level(cursor, text)?
-                )
-            } else {
-                format!("include {}", include)
-            }
-        }
-        "transaction" => {
-            let date = node_text_by_field_name(&node, "date", bytes)?;
-            let txn = node_text_by_field_name(&node, "txn", bytes)?;
+        let accounts_uri = url_from_file_path(&accounts_file_path)?;
+        let mut data = read(&main_file_path)?;
 
-            let txn_strings = node
-                .child_by_field_name("txn_strings")
-                .ok_or_else(|| Error::InvalidState)?
-                .children(&mut node.walk())
-                .collect::<Vec<_>>();
+        assert!(data.contains(&accounts_uri));
+        assert_eq!(
+            data.definitions(SymbolKind::Account, "Assets:Checking")[0].uri,
+            accounts_uri
+        );
 
-            let payee = txn_strings[0].utf8_text(bytes)?;
+        assert_eq!(
+            super::find_root(&accounts_uri, &super::included_by(dir.path())?)?,
+            url_from_file_path(&main_file_path)?
+        );
 
-            let first_line = match txn_strings.len() {
-                1 => {
-                    format!("{} {} {}", date, txn, payee)
-                }
-                2 => {
-                    let narration = txn_strings[1].utf8_text(bytes)?;
-                    format!("{} {} {} {}", date, txn, payee, narration)
-                }
-                _ => return Err(Error::UnexpectedFormat),
-            };
+        let mut accounts_file = File::create(&accounts_file_path)?;
+        writeln!(accounts_file, "2015-01-01 open Assets:Savings")?;
 
-            let posting = node
-                .child_by_field_name("posting_or_kv_list")
-                .ok_or_else(|| Error::InvalidState)?;
+        assert!(data.reload(&accounts_uri)?);
+        assert!(data.names(SymbolKind::Account).contains("Assets:Savings"));
+        assert_eq!(data.files.len(), 2);
 
-            if cursor.goto_next_sibling() {
-                format!(
-                    "{}\n{}{}{}",
-                    first_line,
-                    reformat_postings(&posting, text)?,
-                    newlines(cursor),
-                    reformat_top_level(cursor, text)?
-                )
-            } else {
-                format!("{}\n{}", first_line, reformat_postings(&posting, text)?)
-            }
-        }
-        "\n" => {
-            if cursor.goto_next_sibling() {
-                format!("\n{}", reformat_top_level(cursor, text)?)
-            } else {
-                "".to_string()
-            }
+        assert_eq!(
+            normalize(Path::new("/ledger/2024/../accounts/./bank.beancount")),
+            Path::new("/ledger/accounts/bank.beancount")
+        );
+        assert_eq!(normalize(Path::new("/../a/../../b")), Path::new("/b"));
+        assert_eq!(normalize(Path::new("../a/../../b")), Path::new("../../b"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn include_glob() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
+
+        for (month, payee) in [("02", "bar"), ("01", "foo")] {
+            let mut file =
+                File::create(dir.path().join("2024").join(format!("{}.beancount", month)))?;
+
+            writeln!(
+                file,
+                r#"2024-{}-01 * "{}" "baz"
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR"#,
+                month, payee
+            )?;
         }
-        _ => "".to_string(),
-    };
 
-    Ok(formatted)
-}
+        let main_file_path = dir.path().join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
+        write!(main_file, r#"include "2024/*.beancount""#)?;
+
+        let mut data = read(&main_file_path)?;
+
+        let payees = data.names(SymbolKind::Payee);
+        assert_eq!(payees.len(), 2);
+        assert!(payees.contains("foo"));
+        assert!(payees.contains("bar"));
+
+        let includes = &data.files[&url_from_file_path(&main_file_path)?].includes;
+        assert_eq!(includes.len(), 1);
+        assert_eq!(includes[0].files.len(), 2);
+        assert!(includes[0].files[0].path().ends_with("2024/01.beancount"));
+        assert!(includes[0].files[1].path().ends_with("2024/02.beancount"));
+
+        let watched = data.watched();
+        assert_eq!(watched.len(), 2);
+        assert!(watched
+            .iter()
+            .any(|pattern| pattern.ends_with("/main.beancount")));
+        assert!(watched
+            .iter()
+            .any(|pattern| pattern.ends_with("/2024/*.beancount")));
+
+        let new_file_path = dir.path().join("2024").join("03.beancount");
+        let mut new_file = File::create(&new_file_path)?;
+
+        writeln!(
+            new_file,
+            r#"2024-03-01 * "qux" "baz"
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR"#
+        )?;
 
-pub fn reformat(uri: &Url) -> Result<Option<String>, Error> {
-    let file_path = uri.to_file_path().map_err(|_| Error::UriToPathConversion)?;
-    let text = read_to_string(&file_path)?;
+        assert!(data.reload(&url_from_file_path(&new_file_path)?)?);
+        assert!(data.names(SymbolKind::Payee).contains("qux"));
 
-    let mut parser = tree_sitter::Parser::new();
-    parser.set_language(tree_sitter_beancount::language())?;
+        Ok(())
+    }
 
-    let tree = parser
-        .parse(&text, None)
-        .ok_or_else(|| Error::TreeParseError)?;
+    #[test]
+    fn include_from_glob_directory() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let ledger_dir = dir.path().join("ledger [*]");
+        std::fs::create_dir_all(ledger_dir.join("2024"))?;
 
-    let mut cursor = tree.root_node().walk();
+        let mut accounts_file = File::create(ledger_dir.join("accounts.beancount"))?;
+        writeln!(accounts_file, "2015-01-01 open Assets:Checking")?;
 
-    Ok(Some(reformat_top_level(&mut cursor, &text)?))
-}
+        let mut month_file = File::create(ledger_dir.join("2024").join("01.beancount"))?;
+        writeln!(month_file, "2015-01-01 open Assets:Savings")?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::File;
-    use std::io::Write;
-    use std::path::Path;
-    use tower_lsp::lsp_types::Url;
+        let main_file_path = ledger_dir.join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
 
-    fn url_from_file_path<P: AsRef<Path>>(path: P) -> Result<Url, Error> {
-        Ok(Url::from_file_path(path).map_err(|_| Error::UriToPathConversion)?)
+        write!(
+            main_file,
+            r#"include "accounts.beancount"
+include "2024/*.beancount"
+"#
+        )?;
+
+        let data = read(&main_file_path)?;
+        let accounts = data.names(SymbolKind::Account);
+
+        assert!(accounts.contains("Assets:Checking"));
+        assert!(accounts.contains("Assets:Savings"));
+        assert!(data.diagnostics().is_empty());
+
+        Ok(())
     }
 
-    fn reformat<P: AsRef<Path>>(path: P) -> Result<String, Error> {
-        Ok(super::reformat(&url_from_file_path(path)?)?.ok_or_else(|| Error::UnexpectedFormat)?)
+    #[test]
+    fn include_diagnostics() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let main_file_path = dir.path().join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
+
+        write!(
+            main_file,
+            r#"include "missing.beancount"
+include "cycle.beancount"
+include "2023/*.beancount"
+include "accounts.beancount"
+"#
+        )?;
+
+        let cycle_file_path = dir.path().join("cycle.beancount");
+        let mut cycle_file = File::create(&cycle_file_path)?;
+        write!(cycle_file, r#"include "main.beancount""#)?;
+
+        let accounts_file_path = dir.path().join("accounts.beancount");
+        File::create(&accounts_file_path)?;
+
+        let mut data = read(&main_file_path)?;
+        let diagnostics = data.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+
+        let mut main_diagnostics = diagnostics[&url_from_file_path(&main_file_path)?].clone();
+        main_diagnostics.sort_by_key(|diagnostic| diagnostic.range.start.line);
+        assert_eq!(main_diagnostics.len(), 2);
+        assert_eq!(main_diagnostics[0].range.start.line, 0);
+        assert!(main_diagnostics[0].message.starts_with("Cannot load"));
+        assert_eq!(main_diagnostics[1].range.start.line, 2);
+        assert!(main_diagnostics[1]
+            .message
+            .contains("does not match any files"));
+
+        let cycle_diagnostics = &diagnostics[&url_from_file_path(&cycle_file_path)?];
+        assert_eq!(cycle_diagnostics.len(), 1);
+        assert!(cycle_diagnostics[0].message.starts_with("Cyclic include"));
+
+        // Deleting an included ledger reports it as missing right away.
+        std::fs::remove_file(&accounts_file_path)?;
+        assert!(data.reload(&url_from_file_path(&accounts_file_path)?)?);
+
+        let mut main_diagnostics =
+            data.diagnostics()[&url_from_file_path(&main_file_path)?].clone();
+        main_diagnostics.sort_by_key(|diagnostic| diagnostic.range.start.line);
+        assert_eq!(main_diagnostics.len(), 3);
+        assert_eq!(main_diagnostics[2].range.start.line, 3);
+        assert!(main_diagnostics[2].message.starts_with("Cannot load"));
+
+        Ok(())
     }
 
     #[test]
-    fn parse() -> Result<(), Error> {
+    fn option_diagnostics() -> Result<(), Error> {
         let mut file = tempfile::NamedTempFile::new()?;
 
         write!(
             file.as_file_mut(),
-            r#"2021-07-10 "foo" "bar"
-  Expenses:Cash       100.00 EUR
-  Assets:Checking    -100.00 EUR
-        "#
+            r#"option "title" "Ledger"
+option "operating_curency" "EUR"
+"#
         )?;
 
-        let data = Data::new(&url_from_file_path(file.path())?)?;
+        let data = read(file.path())?;
+        let diagnostics = &data.diagnostics()[&url_from_file_path(file.path())?];
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start.line, 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Unknown option \"operating_curency\""
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn currency_constraints() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking EUR
+2015-01-01 open Expenses:Food
+
+2021-07-10 * "foo" "bar"
+  Expenses:Food       100.00 USD
+  Assets:Checking    -100.00 USD
 
-        assert_eq!(data.accounts.len(), 2);
+2021-07-11 * "foo" "bar"
+  Expenses:Food       100.00 EUR
+  Assets:Checking    -100.00 EUR
+"#
+        )?;
 
-        assert!(data.accounts.contains("Expenses:Cash"));
-        assert!(data.accounts.contains("Assets:Checking"));
+        let data = read(file.path())?;
 
-        assert_eq!(data.currencies.len(), 1);
-        assert!(data.currencies.contains("EUR"));
+        assert_eq!(
+            data.posting_currencies("Expenses:Food"),
+            vec![("EUR", 1), ("USD", 1)]
+        );
 
-        assert_eq!(data.payees.len(), 1);
-        assert!(data.payees.contains("foo"));
+        let diagnostics = &data.diagnostics()[&url_from_file_path(file.path())?];
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start.line, 5);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
 
         Ok(())
     }
 
     #[test]
-    fn commodity_definition() -> Result<(), Error> {
+    fn root_accounts() -> Result<(), Error> {
         let mut file = tempfile::NamedTempFile::new()?;
 
         write!(
             file.as_file_mut(),
-            r#"2015-01-01 commodity USD
-  name: "US Dollar"
-  type: "Currency"
-2015-01-01 commodity EUR
-  name: "Euro"
-  type: "Currency""#
-        )?;
+            r#"option "name_income" "Ertraege"
 
-        let data = Data::new(&url_from_file_path(file.path())?)?;
+2015-01-01 open Ertraege:Gehalt
+2015-01-01 open Income:Salary
+"#
+        )?;
 
-        assert_eq!(data.commodities.len(), 2);
+        let data = read(file.path())?;
+        let uri = url_from_file_path(file.path())?;
 
-        let usd_location = data.commodities.get("USD").unwrap();
-        assert_eq!(usd_location.range.start.line, 0);
+        assert_eq!(
+            data.root_accounts(&uri),
+            vec!["Assets", "Liabilities", "Equity", "Ertraege", "Expenses"]
+        );
 
-        let eur_location = data.commodities.get("EUR").unwrap();
-        assert_eq!(eur_location.range.start.line, 3);
+        let diagnostics = &data.diagnostics()[&uri];
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start.line, 3);
 
         Ok(())
     }
 
     #[test]
-    fn include() -> Result<(), Error> {
+    fn root_accounts_of_several_ledgers() -> Result<(), Error> {
         let dir = tempfile::tempdir()?;
 
-        let commodity_file_path = dir.path().join("commodities.beancount");
-        let mut commodity_file = File::create(&commodity_file_path)?;
+        let main_path = dir.path().join("main.beancount");
+        let mut main = File::create(&main_path)?;
 
         write!(
-            commodity_file,
-            r#"2015-01-01 commodity USD
-  name: "US Dollar"
-  type: "Currency""#
+            main,
+            r#"include "options.beancount"
+
+2015-01-01 open Ertraege:Gehalt
+"#
         )?;
 
-        let main_file_path = dir.path().join("main.beancount");
-        let mut main_file = File::create(&main_file_path)?;
+        let options_path = dir.path().join("options.beancount");
+        let mut options = File::create(&options_path)?;
 
         write!(
-            main_file,
-            r#"include "commodities.beancount"
+            options,
+            r#"option "name_income" "Ertraege"
+option "name_expenses" "Ausgaben"
+"#
+        )?;
+
+        let other_path = dir.path().join("a.beancount");
+        let mut other = File::create(&other_path)?;
+
+        write!(
+            other,
+            r#"option "name_expenses" "Aufwand"
+
+2015-01-01 open Ertraege:Gehalt
+2015-01-01 open Aufwand:Miete
+"#
+        )?;
+
+        let main_uri = url_from_file_path(&main_path)?;
+        let options_uri = url_from_file_path(&options_path)?;
+        let other_uri = url_from_file_path(&other_path)?;
+
+        let mut data = Data::default();
+        data.load(&main_uri)?;
+        data.load(&other_uri)?;
+
+        for uri in [&main_uri, &options_uri] {
+            assert_eq!(
+                data.root_accounts(uri),
+                vec!["Assets", "Liabilities", "Equity", "Ertraege", "Ausgaben"]
+            );
+        }
+
+        assert_eq!(
+            data.root_accounts(&other_uri),
+            vec!["Assets", "Liabilities", "Equity", "Income", "Aufwand"]
+        );
+
+        let diagnostics = data.diagnostics();
+        assert!(!diagnostics.contains_key(&main_uri));
+        assert_eq!(diagnostics[&other_uri].len(), 1);
+        assert_eq!(diagnostics[&other_uri][0].range.start.line, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn decimal() {
+        let parse = |text| Decimal::parse(text).unwrap();
+
+        assert_eq!(parse("-1,234.50").to_string(), "-1234.50");
+        assert_eq!(parse("10").to_string(), "10");
+        assert_eq!(parse("-0.05").to_string(), "-0.05");
+        assert_eq!(
+            parse("100.00")
+                .checked_add(parse("-0.5"))
+                .unwrap()
+                .to_string(),
+            "99.50"
+        );
+        assert_eq!(
+            parse("1")
+                .checked_add(parse("1.25").checked_neg().unwrap())
+                .unwrap()
+                .to_string(),
+            "-0.25"
+        );
+        assert!(Decimal::parse("10/3").is_none());
+
+        let tiny = format!("0.{}1", "0".repeat(38));
+        assert_eq!(parse(&tiny).to_string(), tiny);
+        assert!(parse(&tiny).checked_add(parse("1.00")).is_none());
+        assert!(parse("-170141183460469231731687303715884105728")
+            .checked_neg()
+            .is_none());
+    }
+
+    #[test]
+    fn balance() -> Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 commodity EUR
+  name: "Euro"
 
 2021-07-10 * "foo" "bar"
-  Expenses:Cash       100.00 USD
-  Assets:Checking    -100.00 USD
-        "#
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+
+2021-07-11 * "foo" "bar"
+  Expenses:Food        20.50 EUR
+  Assets:Checking     -20.50 EUR
+
+2021-07-12 * "foo" "bar"
+  Assets:Broker          10 AAPL {{100.00 USD}}
+  Assets:Checking
+
+2021-07-13 * "foo" "bar"
+  Assets:Dust  0.000000000000000000000000000000000000001 EUR
+  Assets:Dust  1.00 EUR
+  Equity:Dust
+"#
         )?;
 
-        let data = Data::new(&url_from_file_path(&main_file_path)?)?;
-        let usd_location = data.commodities.get("USD").unwrap();
-        assert_eq!(usd_location.uri.path(), commodity_file_path.as_os_str());
+        let data = read(file.path())?;
+        let balance = data.balance("Assets:Checking").unwrap();
+
+        assert_eq!(balance.len(), 1);
+        assert_eq!(balance["EUR"].to_string(), "-120.50");
+        assert_eq!(
+            data.balance("Assets:Broker").unwrap()["AAPL"].to_string(),
+            "10"
+        );
+        assert!(data.balance("Assets:Dust").is_none());
+
+        assert_eq!(
+            data.commodity("EUR")
+                .and_then(|commodity| commodity.name.as_deref()),
+            Some("Euro")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_root() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
+
+        let main_file_path = dir.path().join("main.beancount");
+        let mut main_file = File::create(&main_file_path)?;
+        write!(main_file, r#"include "2024/expenses.beancount""#)?;
+
+        let expenses_file_path = dir.path().join("2024").join("expenses.beancount");
+        let mut expenses_file = File::create(&expenses_file_path)?;
+        write!(expenses_file, r#"include "food.beancount""#)?;
+
+        let food_file_path = dir.path().join("2024").join("food.beancount");
+        File::create(&food_file_path)?;
+
+        let other_file_path = dir.path().join("other.beancount");
+        File::create(&other_file_path)?;
+
+        // Symbolic links to directories are not followed, otherwise this would loop.
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("2024").join("loop"))?;
+
+        let included_by = super::included_by(dir.path())?;
+
+        let root = super::find_root(&url_from_file_path(&food_file_path)?, &included_by)?;
+        assert_eq!(root, url_from_file_path(&main_file_path)?);
+
+        let root = super::find_root(&url_from_file_path(&other_file_path)?, &included_by)?;
+        assert_eq!(root, url_from_file_path(&other_file_path)?);
 
         Ok(())
     }
@@ -0,0 +1,215 @@
+use crate::beancount::parse;
+use crate::Error;
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent};
+use tree_sitter::{InputEdit, Point, Tree};
+
+/// An open text document as tracked by the client.
+pub struct Document {
+    pub text: String,
+    pub version: i32,
+    pub tree: Tree,
+}
+
+/// Compute the tree-sitter point of a byte offset.
+fn point(text: &str, offset: usize) -> Point {
+    let before = &text[..offset];
+
+    Point {
+        row: before.matches('\n').count(),
+        column: before.rfind('\n').map_or(offset, |i| offset - i - 1),
+    }
+}
+
+impl Document {
+    pub fn new(text: String, version: i32) -> Result<Self, Error> {
+        let tree = parse(&text, None)?;
+        Ok(Self {
+            text,
+            version,
+            tree,
+        })
+    }
+
+    /// Byte offset of an LSP position, whose character counts UTF-16 code units. Positions
+    /// beyond the end of a line or the document are clamped.
+    pub fn offset(&self, position: Position) -> usize {
+        let start = self
+            .text
+            .split_inclusive('\n')
+            .take(position.line as usize)
+            .map(str::len)
+            .sum::<usize>();
+
+        let line = self.text[start..].split('\n').next().unwrap_or("");
+        let mut units = 0;
+
+        for (index, c) in line.char_indices() {
+            if units >= position.character as usize {
+                return start + index;
+            }
+
+            units += c.len_utf16();
+        }
+
+        start + line.len()
+    }
+
+    /// Tree-sitter point of an LSP position.
+    pub fn point(&self, position: Position) -> Point {
+        point(&self.text, self.offset(position))
+    }
+
+    /// Text of the line at `position` up to the position.
+    pub fn line_before(&self, position: Position) -> &str {
+        let offset = self.offset(position);
+        let start = self.text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        &self.text[start..offset]
+    }
+
+    /// Apply content changes in order and reparse, reusing the old tree for all edits that
+    /// carry a range.
+    pub fn apply_changes(
+        &mut self,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        version: i32,
+    ) -> Result<(), Error> {
+        let mut reuse_tree = true;
+
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start_byte = self.offset(range.start);
+                    let old_end_byte = self.offset(range.end).max(start_byte);
+                    let new_end_byte = start_byte + change.text.len();
+                    let start_position = point(&self.text, start_byte);
+                    let old_end_position = point(&self.text, old_end_byte);
+
+                    self.text
+                        .replace_range(start_byte..old_end_byte, &change.text);
+
+                    self.tree.edit(&InputEdit {
+                        start_byte,
+                        old_end_byte,
+                        new_end_byte,
+                        start_position,
+                        old_end_position,
+                        new_end_position: point(&self.text, new_end_byte),
+                    });
+                }
+                None => {
+                    self.text = change.text;
+                    reuse_tree = false;
+                }
+            }
+        }
+
+        self.tree = parse(&self.text, if reuse_tree { Some(&self.tree) } else { None })?;
+        self.version = version;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::Range;
+
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position {
+                    line: start.0,
+                    character: start.1,
+                },
+                end: Position {
+                    line: end.0,
+                    character: end.1,
+                },
+            }),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn offset_counts_utf16() -> Result<(), Error> {
+        let document = Document::new("2021-07-10 * \"Bäckerei\" \"𝄞\"\n".to_string(), 0)?;
+
+        let position = Position {
+            line: 0,
+            character: 22,
+        };
+
+        assert_eq!(document.offset(position), 23);
+        assert_eq!(document.point(position), Point { row: 0, column: 23 });
+
+        let position = Position {
+            line: 0,
+            character: 27,
+        };
+
+        assert_eq!(&document.text[document.offset(position)..], "\"\n");
+        assert_eq!(
+            document.line_before(position),
+            "2021-07-10 * \"Bäckerei\" \"𝄞"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn incremental_changes() -> Result<(), Error> {
+        let mut document = Document::new(
+            r#"2021-07-10 * "foo" "bar"
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR
+"#
+            .to_string(),
+            0,
+        )?;
+
+        document.apply_changes(
+            vec![
+                change((1, 11), (1, 11), "Food:"),
+                change((2, 2), (2, 17), "Assets:Cash"),
+            ],
+            1,
+        )?;
+
+        assert_eq!(
+            document.text,
+            r#"2021-07-10 * "foo" "bar"
+  Expenses:Food:Cash       100.00 EUR
+  Assets:Cash    -100.00 EUR
+"#
+        );
+
+        assert_eq!(document.version, 1);
+
+        let full = parse(&document.text, None)?;
+        assert_eq!(
+            document.tree.root_node().to_sexp(),
+            full.root_node().to_sexp()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn full_change() -> Result<(), Error> {
+        let mut document = Document::new("option \"title\" \"foo\"\n".to_string(), 0)?;
+
+        document.apply_changes(
+            vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "option \"title\" \"bar\"\n".to_string(),
+            }],
+            1,
+        )?;
+
+        assert_eq!(document.text, "option \"title\" \"bar\"\n");
+
+        Ok(())
+    }
+}
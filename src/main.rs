@@ -1,3 +1,6 @@
+use beancount::SymbolKind;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::convert::From;
 use std::env;
 use std::fmt::Display;
@@ -8,9 +11,10 @@ use tokio::sync::RwLock;
 use tower_lsp::jsonrpc::{ErrorCode, Result};
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
-use tree_sitter::{Language, Node};
+use tree_sitter::Node;
 
 mod beancount;
+mod document;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -40,6 +44,18 @@ pub enum Error {
 
     #[error("Invalid state")]
     InvalidState,
+
+    #[error("JSON error")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("Task join error")]
+    JoinError(#[from] tokio::task::JoinError),
+
+    #[error("Invalid name \"{0}\"")]
+    InvalidName(String),
+
+    #[error("\"{0}\" already exists")]
+    AlreadyExists(String),
 }
 
 impl From<Error> for tower_lsp::jsonrpc::Error {
@@ -54,50 +70,880 @@ impl From<Error> for tower_lsp::jsonrpc::Error {
 
 #[derive(Default)]
 struct State {
+    /// Index shared by all ledger files.
     data: beancount::Data,
+    /// Currently opened documents.
+    documents: HashMap<Url, document::Document>,
+    /// Root ledger from which the index is built, if configured by the client.
+    journal_file: Option<Url>,
+    /// References to an account include those to its sub-accounts.
+    references_include_subaccounts: bool,
+    /// Renaming an account renames its sub-accounts as well.
+    rename_include_subaccounts: bool,
+    /// Workspace directory used to discover the root ledger.
+    workspace: Option<PathBuf>,
+    /// Files of the workspace by the ledger including them, discovered on demand and dropped
+    /// whenever a ledger changes on disk.
+    included_by: Option<Arc<HashMap<PathBuf, PathBuf>>>,
+    /// Client supports watching ledger files for changes.
+    watch_files: bool,
+    /// Glob patterns of the files the client watches.
+    watched: Vec<String>,
+    /// Client accepts workspace edits versioned by document.
+    document_changes: bool,
+    /// Diagnostics reported by bean-check.
+    checks: HashMap<Url, Vec<Diagnostic>>,
+    /// Documents with diagnostics published to the client.
+    published: HashSet<Url>,
 }
 
+/// Directive keywords with a snippet of their required fields and a description.
+const DIRECTIVES: [(&str, &str, &str); 14] = [
+    (
+        "*",
+        "* \"${1:payee}\" \"${2:narration}\"",
+        "completed transaction",
+    ),
+    (
+        "!",
+        "! \"${1:payee}\" \"${2:narration}\"",
+        "incomplete transaction",
+    ),
+    (
+        "txn",
+        "txn \"${1:payee}\" \"${2:narration}\"",
+        "transaction",
+    ),
+    ("open", "open ${1:account}", "open an account"),
+    ("close", "close ${1:account}", "close an account"),
+    (
+        "balance",
+        "balance ${1:account} ${2:number} ${3:currency}",
+        "assert the balance of an account",
+    ),
+    (
+        "pad",
+        "pad ${1:account} ${2:source}",
+        "pad an account from another",
+    ),
+    (
+        "note",
+        "note ${1:account} \"${2:description}\"",
+        "attach a note to an account",
+    ),
+    (
+        "document",
+        "document ${1:account} \"${2:path}\"",
+        "attach a document to an account",
+    ),
+    (
+        "price",
+        "price ${1:commodity} ${2:number} ${3:currency}",
+        "price of a commodity",
+    ),
+    (
+        "event",
+        "event \"${1:type}\" \"${2:description}\"",
+        "change of an event variable",
+    ),
+    ("query", "query \"${1:name}\" \"${2:sql}\"", "named query"),
+    ("custom", "custom \"${1:type}\"", "custom directive"),
+    (
+        "commodity",
+        "commodity ${1:currency}",
+        "declare a commodity",
+    ),
+];
+
 fn node_text<'a>(node: &'a Node, text: &'a str) -> Result<&'a str> {
     Ok(node.utf8_text(text.as_bytes()).map_err(Error::from)?)
 }
 
+/// Data of a completion item identifying the symbol to resolve documentation for.
+fn resolve_data(kind: &str, name: &str) -> serde_json::Value {
+    serde_json::json!({ "kind": kind, "name": name })
+}
+
+/// Range of `prefix` typed right before `position`.
+fn prefix_range(prefix: &str, position: Position) -> Range {
+    Range {
+        start: Position {
+            line: position.line,
+            character: position.character - prefix.encode_utf16().count() as u32,
+        },
+        end: position,
+    }
+}
+
+/// Kind and name of the symbol `node` refers to, with the name as indexed.
+fn symbol_at(node: &Node, text: &str) -> Option<(SymbolKind, String)> {
+    let kind = match node.kind() {
+        "account" => SymbolKind::Account,
+        "currency" => SymbolKind::Commodity,
+        "tag" => SymbolKind::Tag,
+        "link" => SymbolKind::Link,
+        "string"
+            if node
+                .parent()
+                .filter(|parent| parent.kind() == "txn_strings")
+                .and_then(|parent| beancount::payee(&parent))
+                .is_some_and(|payee| payee.id() == node.id()) =>
+        {
+            SymbolKind::Payee
+        }
+        _ => return None,
+    };
+
+    let name = node
+        .utf8_text(text.as_bytes())
+        .ok()?
+        .trim_start_matches(['#', '^'])
+        .trim_matches('"');
+
+    Some((kind, name.to_string()))
+}
+
+/// Ranges of the paths of all `include` and `document` directives of a document and the
+/// existing files they refer to, resolved relative to the document and with globs expanded.
+fn linked_paths(uri: &Url, document: &document::Document) -> Result<Vec<(Range, Vec<Url>)>> {
+    let file_path = uri.to_file_path().map_err(|_| Error::UriToPathConversion)?;
+
+    Ok(["include", "document"]
+        .iter()
+        .flat_map(|kind| beancount::paths(&document.tree, &document.text, &file_path, kind))
+        .map(|directive| {
+            let files = directive
+                .expand()
+                .into_iter()
+                .filter(|path| path.is_file())
+                .filter_map(|path| beancount::file_url(&path).ok())
+                .collect();
+
+            (directive.range, files)
+        })
+        .collect())
+}
+
 fn item_from_str<T: Into<String>>(label: T) -> CompletionItem {
     CompletionItem::new_simple(label.into(), "".to_string())
 }
 
+/// Completion items of names ranked by their number of occurrences.
+fn ranked_items(ranked: Vec<(&str, usize)>) -> Vec<CompletionItem> {
+    ranked
+        .into_iter()
+        .enumerate()
+        .map(|(rank, (name, count))| CompletionItem {
+            sort_text: Some(format!("{:06}", rank)),
+            ..CompletionItem::new_simple(name.to_string(), format!("used {} times", count))
+        })
+        .collect()
+}
+
+/// Metadata being typed on an indented line.
+enum MetadataPrefix<'a> {
+    /// A key typed so far, which unlike an account starts with a lowercase letter.
+    Key(&'a str),
+    /// The value of a key after its colon.
+    Value(&'a str),
+}
+
+fn is_metadata_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Detect metadata from the text of a line before the cursor.
+fn metadata_prefix(line: &str) -> Option<MetadataPrefix<'_>> {
+    let trimmed = line.trim_start();
+
+    if trimmed.len() == line.len() {
+        return None;
+    }
+
+    match trimmed.split_once(':') {
+        Some((key, _)) if is_metadata_key(key) => Some(MetadataPrefix::Value(key)),
+        None if is_metadata_key(trimmed) => Some(MetadataPrefix::Key(trimmed)),
+        _ => None,
+    }
+}
+
+/// Match `query` against `account` ignoring case and return a score, lower is better. Each
+/// colon-separated part of the query being a prefix of an account segment, in order, is a
+/// better match than the query being a subsequence of the account. Both `e:f:gro` and `groc`
+/// match `Expenses:Food:Groceries`.
+fn account_match(query: &str, account: &str) -> Option<u8> {
+    let query = query.to_lowercase();
+    let account = account.to_lowercase();
+    let mut segments = account.split(':');
+
+    if query
+        .split(':')
+        .all(|part| segments.any(|segment| segment.starts_with(part)))
+    {
+        return Some(0);
+    }
+
+    let mut chars = account.chars();
+
+    if query.chars().all(|c| chars.any(|other| other == c)) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// Account typed so far, i.e. the last word of a line before the cursor.
+fn account_query(line: &str) -> &str {
+    line.rsplit(char::is_whitespace).next().unwrap_or("")
+}
+
+/// Detect the directive keyword typed so far from the text of a line before the cursor, i.e.
+/// the word following a date at the start of the line.
+fn keyword_prefix(line: &str) -> Option<&str> {
+    let (date, keyword) = line.split_once(char::is_whitespace)?;
+    let keyword = keyword.trim_start();
+
+    let is_date = date.len() == 10
+        && date.char_indices().all(|(index, c)| match index {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+
+    if is_date && !keyword.starts_with('"') && !keyword.contains(char::is_whitespace) {
+        Some(keyword)
+    } else {
+        None
+    }
+}
+
+/// Detect the account and the currency typed so far from the text of a posting line before
+/// the cursor, i.e. if the cursor follows the number of the amount.
+fn currency_prefix(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start();
+
+    if trimmed.len() == line.len() {
+        return None;
+    }
+
+    let words = trimmed.split_whitespace().collect::<Vec<_>>();
+    let after_space = line.ends_with(char::is_whitespace);
+
+    let (account, number, prefix) = match words[..] {
+        [account, number] if after_space => (account, number, ""),
+        [account, number, prefix] if !after_space => (account, number, prefix),
+        _ => return None,
+    };
+
+    let is_account = account.starts_with(|c: char| c.is_ascii_uppercase());
+    let is_number = number.starts_with(|c: char| c.is_ascii_digit() || "+-.(".contains(c));
+
+    if is_account && is_number {
+        Some((account, prefix))
+    } else {
+        None
+    }
+}
+
+/// Detect a path from the text of a line before the cursor, i.e. inside the string of an
+/// `include` or `document` directive. Returns the directive keyword and the path typed so far.
+fn path_prefix(line: &str) -> Option<(&str, &str)> {
+    let (head, path) = line.split_once('"')?;
+
+    if path.contains('"') {
+        return None;
+    }
+
+    match head.split_whitespace().collect::<Vec<_>>()[..] {
+        ["include"] => Some(("include", path)),
+        [_, "document", _] => Some(("document", path)),
+        _ => None,
+    }
+}
+
+/// Detect an option from the text of a line before the cursor. Returns the option name typed
+/// so far, or the option name and the value typed so far if the cursor is inside the value.
+fn option_prefix(line: &str) -> Option<(Option<&str>, &str)> {
+    if !line.starts_with("option") {
+        return None;
+    }
+
+    match line.split('"').collect::<Vec<_>>()[..] {
+        [_, name] => Some((None, name)),
+        [_, name, separator, value] if separator.trim().is_empty() => Some((Some(name), value)),
+        _ => None,
+    }
+}
+
+/// Detect the payee and the narration typed so far from the text of a transaction line before
+/// the cursor, i.e. if the cursor is inside the second string.
+fn narration_prefix(line: &str) -> Option<(&str, &str)> {
+    if !line.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    match line.split('"').collect::<Vec<_>>()[..] {
+        [_, payee, separator, narration] if separator.trim().is_empty() => Some((payee, narration)),
+        _ => None,
+    }
+}
+
 impl State {
-    fn complete_account(&self) -> Result<Option<CompletionResponse>> {
-        Ok(Some(CompletionResponse::Array(
-            self.data.accounts.iter().map(item_from_str).collect(),
-        )))
+    /// Complete the account `query` at `position` including the root accounts, best matches
+    /// first and among them the most frequently and then most recently used accounts. Accounts
+    /// that are not open on the `date` of the enclosing directive, or closed if there is none,
+    /// come last.
+    fn complete_account(
+        &self,
+        uri: &Url,
+        query: &str,
+        position: Position,
+        date: Option<&str>,
+    ) -> Result<Option<CompletionResponse>> {
+        let usage = self.data.usage(SymbolKind::Account);
+        let mut accounts = self.data.accounts();
+
+        for root in self.data.root_accounts(uri) {
+            accounts.entry(root).or_default();
+        }
+
+        let mut candidates = accounts
+            .into_iter()
+            .filter_map(|(name, account)| {
+                let score = account_match(query, name)?;
+                let inactive = match date {
+                    Some(date) => !account.is_open(date),
+                    None => account.close.is_some(),
+                };
+                let usage = usage.get(name);
+                let count = usage.map_or(0, |usage| usage.count);
+                let last = usage.and_then(|usage| usage.last);
+                Some((
+                    inactive,
+                    score,
+                    Reverse(count),
+                    Reverse(last),
+                    name,
+                    account,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        candidates.sort_by(|a, b| (a.0, a.1, a.2, a.3, a.4).cmp(&(b.0, b.1, b.2, b.3, b.4)));
+
+        let range = prefix_range(query, position);
+
+        let items = candidates
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (inactive, _, _, _, name, account))| {
+                let mut item = item_from_str(name);
+                item.data = Some(resolve_data("account", name));
+                item.sort_text = Some(format!("{:06}", rank));
+                item.filter_text = Some(query.to_string());
+                item.text_edit = Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: name.to_string(),
+                }));
+
+                if inactive {
+                    item.tags = Some(vec![CompletionItemTag::DEPRECATED]);
+                }
+
+                if let Some(close) = account.close {
+                    item.detail = Some(format!("closed {}", close.date));
+                } else if let Some(open) = account.open {
+                    item.detail = Some(if open.currencies.is_empty() {
+                        format!("opened {}", open.date)
+                    } else {
+                        format!("opened {} ({})", open.date, open.currencies.join(", "))
+                    });
+                }
+
+                item
+            })
+            .collect();
+
+        // The client has to ask again while typing because its own filtering differs.
+        Ok(Some(CompletionResponse::List(CompletionList {
+            is_incomplete: true,
+            items,
+        })))
+    }
+
+    /// Complete the currency `prefix` at `position`. For a posting to `account`, its constraint
+    /// currencies come first, then currencies previously used with it and then all others,
+    /// which are marked if they violate the constraint.
+    fn complete_currency(
+        &self,
+        account: Option<&str>,
+        prefix: &str,
+        position: Position,
+    ) -> Result<Option<CompletionResponse>> {
+        let accounts = self.data.accounts();
+
+        let constraints = account
+            .and_then(|account| accounts.get(account))
+            .and_then(|account| account.open)
+            .map_or(&[][..], |open| open.currencies.as_slice());
+
+        let mut candidates = constraints
+            .iter()
+            .map(|currency| (currency.as_str(), "allowed".to_string()))
+            .collect::<Vec<_>>();
+
+        if let Some(account) = account {
+            for (currency, count) in self.data.posting_currencies(account) {
+                if !constraints.iter().any(|other| other == currency) {
+                    candidates.push((currency, format!("used {} times", count)));
+                }
+            }
+        }
+
+        let mut others = self
+            .data
+            .names(SymbolKind::Commodity)
+            .into_iter()
+            .filter(|currency| candidates.iter().all(|(other, _)| other != currency))
+            .collect::<Vec<_>>();
+
+        others.sort_unstable();
+        candidates.extend(others.into_iter().map(|currency| (currency, String::new())));
+
+        let range = prefix_range(prefix, position);
+
+        let items = candidates
+            .into_iter()
+            .filter(|(currency, _)| currency.starts_with(prefix))
+            .enumerate()
+            .map(|(rank, (currency, detail))| {
+                let mut item = CompletionItem::new_simple(currency.to_string(), detail);
+                item.data = Some(resolve_data("commodity", currency));
+                item.sort_text = Some(format!("{:06}", rank));
+                item.text_edit = Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: currency.to_string(),
+                }));
+
+                if !constraints.is_empty() && !constraints.iter().any(|other| other == currency) {
+                    item.detail = Some(format!("not allowed in {}", account.unwrap_or("")));
+                    item.tags = Some(vec![CompletionItemTag::DEPRECATED]);
+                }
+
+                item
+            })
+            .collect();
+
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    /// Complete symbols such as tags or links, most frequently used first.
+    fn complete_ranked(&self, kind: SymbolKind) -> Result<Option<CompletionResponse>> {
+        Ok(Some(CompletionResponse::Array(ranked_items(
+            self.data.ranked(kind),
+        ))))
+    }
+
+    /// Complete metadata keys or the values of a key previously used with the same kind of
+    /// directive.
+    fn complete_metadata(
+        &self,
+        prefix: MetadataPrefix,
+        directive: &str,
+    ) -> Result<Option<CompletionResponse>> {
+        let ranked = match prefix {
+            MetadataPrefix::Key(prefix) => self
+                .data
+                .metadata_keys(directive)
+                .into_iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .collect(),
+            MetadataPrefix::Value(key) => self.data.metadata_values(directive, key),
+        };
+
+        if ranked.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(CompletionResponse::Array(ranked_items(ranked))))
+        }
     }
 
-    fn complete_currency(&self) -> Result<Option<CompletionResponse>> {
+    /// Complete the directive keyword `prefix` at `position` with a snippet of its required
+    /// fields.
+    fn complete_keyword(
+        &self,
+        prefix: &str,
+        position: Position,
+    ) -> Result<Option<CompletionResponse>> {
+        let range = prefix_range(prefix, position);
+
         Ok(Some(CompletionResponse::Array(
-            self.data.currencies.iter().map(item_from_str).collect(),
+            DIRECTIVES
+                .iter()
+                .filter(|(keyword, _, _)| keyword.starts_with(prefix))
+                .map(|(keyword, snippet, detail)| CompletionItem {
+                    kind: Some(CompletionItemKind::KEYWORD),
+                    insert_text_format: Some(InsertTextFormat::SNIPPET),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range,
+                        new_text: snippet.to_string(),
+                    })),
+                    ..CompletionItem::new_simple(keyword.to_string(), detail.to_string())
+                })
+                .collect(),
         )))
     }
 
-    fn handle_identifier(&self, node: &Node) -> Result<Option<CompletionResponse>> {
-        // This happens for initial completions, i.e. if a character has not triggered
-        // yet. This means this is likely one of the top-level accounts or a payee.
-        let identifier = node_text(node, &self.data.text)?;
+    /// Complete the path `prefix` at `position` relative to the ledger `uri`. Includes are
+    /// completed with directories and ledgers, documents with directories and any file.
+    fn complete_path(
+        &self,
+        uri: &Url,
+        keyword: &str,
+        prefix: &str,
+        position: Position,
+    ) -> Result<Option<CompletionResponse>> {
+        let file_path = uri.to_file_path().map_err(|_| Error::UriToPathConversion)?;
+
+        let (directory, name) = match prefix.rsplit_once('/') {
+            Some((directory, name)) => (format!("{}/", directory), name),
+            None => (String::new(), prefix),
+        };
 
-        for account in ["Expenses", "Assets", "Liabilities", "Equity", "Revenue"] {
-            // Yes, for some stupid reason, the first character is matched as an ERROR
-            // and the identifier starts with the second character ...
-            if account[1..].starts_with(identifier) {
-                return Ok(Some(CompletionResponse::Array(vec![item_from_str(
-                    account,
-                )])));
+        let directory = match file_path.parent() {
+            Some(parent) => parent.join(&directory),
+            None => PathBuf::from(&directory),
+        };
+
+        let entries = match std::fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(None),
+        };
+
+        let range = prefix_range(name, position);
+
+        let mut items = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| *path != file_path)
+            .filter_map(|path| {
+                let file_name = path.file_name()?.to_str()?.to_string();
+
+                if !file_name.starts_with(name) || (file_name.starts_with('.') && name.is_empty()) {
+                    return None;
+                }
+
+                let (label, kind) = if path.is_dir() {
+                    (format!("{}/", file_name), CompletionItemKind::FOLDER)
+                } else if keyword == "document"
+                    || path
+                        .extension()
+                        .is_some_and(|extension| extension == "beancount" || extension == "bean")
+                {
+                    (file_name, CompletionItemKind::FILE)
+                } else {
+                    return None;
+                };
+
+                Some(CompletionItem {
+                    kind: Some(kind),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range,
+                        new_text: label.clone(),
+                    })),
+                    ..item_from_str(label)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        items.sort_by(|a, b| a.label.cmp(&b.label));
+
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    /// Complete the option name or the value of an enumerated option `prefix` at `position`.
+    fn complete_option(
+        &self,
+        name: Option<&str>,
+        prefix: &str,
+        position: Position,
+    ) -> Result<Option<CompletionResponse>> {
+        let candidates = match name {
+            Some(name) => beancount::OPTIONS
+                .iter()
+                .find(|(option, _)| *option == name)
+                .map_or(&[][..], |(_, values)| values)
+                .to_vec(),
+            None => beancount::OPTIONS
+                .iter()
+                .map(|(option, _)| *option)
+                .collect(),
+        };
+
+        let range = prefix_range(prefix, position);
+
+        let items = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(prefix))
+            .map(|candidate| CompletionItem {
+                kind: Some(match name {
+                    Some(_) => CompletionItemKind::ENUM_MEMBER,
+                    None => CompletionItemKind::PROPERTY,
+                }),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: candidate.to_string(),
+                })),
+                ..item_from_str(candidate)
+            })
+            .collect::<Vec<_>>();
+
+        if items.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(CompletionResponse::Array(items)))
+        }
+    }
+
+    /// Complete the narration at `position`, preferring narrations used with `payee`.
+    fn complete_narration(
+        &self,
+        payee: &str,
+        prefix: &str,
+        position: Position,
+    ) -> Result<Option<CompletionResponse>> {
+        let range = prefix_range(prefix, position);
+
+        let candidates = ranked_items(
+            self.data
+                .narrations(payee)
+                .into_iter()
+                .filter(|(narration, _)| narration.starts_with(prefix))
+                .collect(),
+        )
+        .into_iter()
+        .map(|item| CompletionItem {
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                range,
+                new_text: item.label.clone(),
+            })),
+            filter_text: Some(item.label.clone()),
+            ..item
+        })
+        .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(CompletionResponse::Array(candidates)))
+        }
+    }
+
+    /// Complete the postings of a new `transaction` from the most recent transaction with the
+    /// same payee that has postings. Numbers become snippet placeholders and tags missing from
+    /// the header are appended to it.
+    fn complete_postings(
+        &self,
+        transaction: &Node,
+        text: &str,
+        position: Position,
+    ) -> Result<Option<CompletionResponse>> {
+        let payee = match transaction
+            .child_by_field_name("txn_strings")
+            .as_ref()
+            .and_then(beancount::payee)
+        {
+            Some(payee) => payee
+                .utf8_text(text.as_bytes())
+                .map_err(Error::from)?
+                .trim_matches('"'),
+            None => return Ok(None),
+        };
+
+        let last = match self.data.last_transaction(payee) {
+            Some(last) => last,
+            None => return Ok(None),
+        };
+
+        let mut placeholder = 0;
+
+        let postings = last
+            .postings
+            .iter()
+            .map(|posting| match (&posting.number, &posting.currency) {
+                (Some(number), Some(currency)) => {
+                    placeholder += 1;
+                    format!(
+                        "  {}  ${{{}:{}}} {}",
+                        posting.account, placeholder, number, currency
+                    )
+                }
+                _ => format!("  {}", posting.account),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let row = transaction.start_position().row;
+        let mut cursor = transaction.walk();
+
+        let header_end = transaction
+            .children(&mut cursor)
+            .filter(|child| child.start_position().row == row && child.kind() != "comment")
+            .last()
+            .map(|child| beancount::range(&child, text).end);
+
+        let header = text.lines().nth(row).unwrap_or("");
+
+        let tags = last
+            .tags
+            .iter()
+            .map(|tag| format!("#{}", tag))
+            .filter(|tag| !header.split_whitespace().any(|word| word == tag))
+            .collect::<Vec<_>>();
+
+        let additional_text_edits = match header_end {
+            Some(end) if !tags.is_empty() => Some(vec![TextEdit {
+                range: Range { start: end, end },
+                new_text: format!(" {}", tags.join(" ")),
+            }]),
+            _ => None,
+        };
+
+        Ok(Some(CompletionResponse::Array(vec![CompletionItem {
+            label: payee.to_string(),
+            kind: Some(CompletionItemKind::SNIPPET),
+            detail: Some(format!("postings of {}", last.date)),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                range: Range {
+                    start: Position {
+                        line: position.line,
+                        character: 0,
+                    },
+                    end: position,
+                },
+                new_text: postings,
+            })),
+            additional_text_edits,
+            ..CompletionItem::default()
+        }])))
+    }
+
+    /// Describe an account by its `open` and `close` directives, balance and last use.
+    fn describe_account(&self, name: &str) -> Option<String> {
+        let accounts = self.data.accounts();
+        let account = accounts.get(name)?;
+        let mut lines = Vec::new();
+
+        if let Some(open) = account.open {
+            lines.push(format!("Opened {}", open.date));
+
+            if !open.currencies.is_empty() {
+                lines.push(format!("Currencies: {}", open.currencies.join(", ")));
+            }
+
+            if let Some(booking) = &open.booking {
+                lines.push(format!("Booking: {}", booking));
             }
         }
 
-        Ok(None)
+        if let Some(close) = account.close {
+            lines.push(format!("Closed {}", close.date));
+        }
+
+        let balance = self
+            .data
+            .balance(name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(currency, number)| format!("{} {}", number, currency))
+            .collect::<Vec<_>>();
+
+        if !balance.is_empty() {
+            lines.push(format!("Balance: {}", balance.join(", ")));
+        }
+
+        if let Some(last) = self
+            .data
+            .usage(SymbolKind::Account)
+            .get(name)
+            .and_then(|usage| usage.last)
+        {
+            lines.push(format!("Last used {}", last));
+        }
+
+        Some(lines.join("\n\n"))
+    }
+
+    /// Describe a payee by its most recent transaction.
+    fn describe_payee(&self, name: &str) -> Option<String> {
+        let transaction = self.data.last_transaction(name)?;
+
+        let mut header = format!("{} {} \"{}\"", transaction.date, transaction.flag, name);
+
+        if let Some(narration) = &transaction.narration {
+            header.push_str(&format!(" \"{}\"", narration));
+        }
+
+        for tag in &transaction.tags {
+            header.push_str(&format!(" #{}", tag));
+        }
+
+        let postings =
+            transaction
+                .postings
+                .iter()
+                .map(|posting| match (&posting.number, &posting.currency) {
+                    (Some(number), Some(currency)) => {
+                        format!("  {}  {} {}", posting.account, number, currency)
+                    }
+                    _ => format!("  {}", posting.account),
+                });
+
+        Some(format!(
+            "```beancount\n{}\n```",
+            std::iter::once(header)
+                .chain(postings)
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+    }
+
+    /// Describe a commodity by the `name` metadata of its `commodity` directive.
+    fn describe_commodity(&self, name: &str) -> Option<String> {
+        self.data.commodity(name)?.name.clone()
+    }
+
+    /// Fill in the documentation of a completion item.
+    fn resolve(&self, mut item: CompletionItem) -> CompletionItem {
+        let field = |key| {
+            item.data
+                .as_ref()
+                .and_then(|data| data.get(key))
+                .and_then(|value| value.as_str())
+        };
+
+        let documentation = match (field("kind"), field("name")) {
+            (Some("account"), Some(name)) => self.describe_account(name),
+            (Some("payee"), Some(name)) => self.describe_payee(name),
+            (Some("commodity"), Some(name)) => self.describe_commodity(name),
+            _ => None,
+        };
+
+        if let Some(value) = documentation.filter(|value| !value.is_empty()) {
+            item.documentation = Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }));
+        }
+
+        item
     }
 
-    fn handle_error(&self, node: &Node) -> Result<Option<CompletionResponse>> {
-        let identifier = node_text(node, &self.data.text)?;
+    fn handle_error(&self, node: &Node, text: &str) -> Result<Option<CompletionResponse>> {
+        let identifier = node_text(node, text)?;
 
         // Probably, hopefully starts with " and ends with some weird character yet to be
         // identified.
@@ -105,10 +951,13 @@ impl State {
 
         let candidates = self
             .data
-            .payees
-            .iter()
+            .names(SymbolKind::Payee)
+            .into_iter()
             .filter(|p| p.starts_with(prefix))
-            .map(item_from_str)
+            .map(|payee| CompletionItem {
+                data: Some(resolve_data("payee", payee)),
+                ..item_from_str(payee)
+            })
             .collect::<Vec<_>>();
 
         if candidates.is_empty() {
@@ -118,12 +967,22 @@ impl State {
         }
     }
 
-    fn handle_node(&self, node: &Node) -> Result<Option<CompletionResponse>> {
+    fn handle_node(
+        &self,
+        uri: &Url,
+        node: &Node,
+        text: &str,
+        line: &str,
+        position: Position,
+        date: Option<&str>,
+    ) -> Result<Option<CompletionResponse>> {
         match node.kind() {
-            "currency" => self.complete_currency(),
-            "identifier" => self.handle_identifier(node),
-            "account" => self.complete_account(),
-            "ERROR" => self.handle_error(node),
+            "currency" => self.complete_currency(None, account_query(line), position),
+            "identifier" => self.complete_account(uri, account_query(line), position, date),
+            "account" => self.complete_account(uri, account_query(line), position, date),
+            "tag" => self.complete_ranked(SymbolKind::Tag),
+            "link" => self.complete_ranked(SymbolKind::Link),
+            "ERROR" => self.handle_error(node, text),
             _ => Ok(None),
         }
     }
@@ -134,7 +993,6 @@ struct Backend {
     check_cmd: Option<PathBuf>,
     check_re: regex::Regex,
     state: Arc<RwLock<State>>,
-    language: Language,
 }
 
 impl Backend {
@@ -155,39 +1013,229 @@ impl Backend {
             client: Some(client),
             check_cmd,
             check_re: regex::Regex::new(r"^[^:]+:(\d+):\s*(.*)$").expect("failed to compile regex"),
-            language: tree_sitter_beancount::language(),
             state: Arc::new(RwLock::new(State::default())),
         }
     }
 
-    /// Load ledger to search trie and lines.
+    /// Load the root ledger of `uri` and its includes into the shared index unless already
+    /// indexed. The root is either the configured journal file or the ledger including `uri`.
     async fn load_ledgers(&self, uri: &Url) -> Result<()> {
+        let uri = &beancount::normalize_url(uri);
+
+        let (journal_file, workspace, included_by) = {
+            let state = self.state.read().await;
+
+            if state.data.contains(uri) {
+                return Ok(());
+            }
+
+            (
+                state.journal_file.clone(),
+                state.workspace.clone(),
+                state.included_by.clone(),
+            )
+        };
+
+        let root = match (journal_file, workspace) {
+            (Some(journal_file), _) => journal_file,
+            (None, Some(workspace)) => {
+                let included_by = match included_by {
+                    Some(included_by) => included_by,
+                    None => {
+                        // Scan the workspace without holding the lock, it may take a while.
+                        let included_by =
+                            tokio::task::spawn_blocking(move || beancount::included_by(&workspace))
+                                .await
+                                .map_err(Error::from)??;
+
+                        let included_by = Arc::new(included_by);
+                        self.state.write().await.included_by = Some(included_by.clone());
+                        included_by
+                    }
+                };
+
+                beancount::find_root(uri, &included_by)?
+            }
+            (None, None) => uri.clone(),
+        };
+
         let mut state = self.state.write().await;
-        state.data = beancount::Data::new(uri)?;
+
+        if !state.data.contains(&root) {
+            state.data.load(&root)?;
+        }
+
+        // The ledger is not part of the root ledger, so index it on its own.
+        if !state.data.contains(uri) {
+            state.data.load(uri)?;
+        }
+
         Ok(())
     }
 
-    async fn log_message<M: Display>(&self, typ: MessageType, message: M) {
-        if let Some(client) = &self.client {
-            client.log_message(typ, message).await;
-        }
+    /// Re-index a ledger that changed on disk.
+    async fn reload_ledger(&self, uri: &Url) -> Result<()> {
+        let mut state = self.state.write().await;
+        state.included_by = None;
+        state.data.reload(uri)?;
+        Ok(())
     }
 
-    async fn check(&self, uri: Url) -> Result<()> {
-        let client = self.client.as_ref().ok_or(Error::InvalidState)?;
+    /// Ask the client to notify us about changes of ledger files on disk, i.e. those in the
+    /// workspace and all included paths, which may have other extensions or lie outside of the
+    /// workspace. The watchers are registered again whenever the included paths change.
+    async fn watch_ledgers(&self) -> Result<()> {
+        let mut state = self.state.write().await;
 
-        let check_cmd = self.check_cmd.as_ref().ok_or(Error::InvalidState)?;
+        if !state.watch_files {
+            return Ok(());
+        }
 
-        let output = Command::new(check_cmd)
-            .arg(uri.path())
-            .output()
-            .await
-            .map_err(Error::from)?;
+        let client = self.client.as_ref().ok_or(Error::InvalidState)?;
 
-        let diags = if !output.status.success() {
-            let output = std::str::from_utf8(&output.stderr).map_err(Error::from)?;
+        let patterns = ["**/*.beancount", "**/*.bean"]
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .chain(state.data.watched())
+            .collect::<Vec<_>>();
 
-            output
+        if patterns == state.watched {
+            return Ok(());
+        }
+
+        let registered = !state.watched.is_empty();
+        state.watched = patterns.clone();
+        drop(state);
+
+        if registered {
+            client
+                .unregister_capability(vec![Unregistration {
+                    id: "watch-ledgers".to_string(),
+                    method: "workspace/didChangeWatchedFiles".to_string(),
+                }])
+                .await?;
+        }
+
+        let watchers = patterns
+            .into_iter()
+            .map(|pattern| FileSystemWatcher {
+                glob_pattern: pattern,
+                kind: None,
+            })
+            .collect();
+
+        let options = DidChangeWatchedFilesRegistrationOptions { watchers };
+
+        client
+            .register_capability(vec![Registration {
+                id: "watch-ledgers".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: Some(serde_json::to_value(options).map_err(Error::from)?),
+            }])
+            .await
+    }
+
+    async fn configure(&self, params: InitializeParams) -> Result<()> {
+        let workspace = match params.root_uri {
+            Some(uri) => Some(uri.to_file_path().map_err(|_| Error::UriToPathConversion)?),
+            None => None,
+        };
+
+        let journal_file = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("journal_file"))
+            .and_then(|journal_file| journal_file.as_str())
+            .map(|journal_file| {
+                let path = PathBuf::from(journal_file);
+
+                let path = match &workspace {
+                    Some(workspace) if path.is_relative() => workspace.join(path),
+                    _ => path,
+                };
+
+                beancount::file_url(&path)
+            })
+            .transpose()?;
+
+        let references_include_subaccounts = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("references_include_subaccounts"))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+
+        let rename_include_subaccounts = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("rename_include_subaccounts"))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+
+        let watch_files = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files)
+            .and_then(|capabilities| capabilities.dynamic_registration)
+            .unwrap_or(false);
+
+        let document_changes = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .and_then(|capabilities| capabilities.document_changes)
+            .unwrap_or(false);
+
+        let mut state = self.state.write().await;
+        state.workspace = workspace;
+        state.journal_file = journal_file;
+        state.references_include_subaccounts = references_include_subaccounts;
+        state.rename_include_subaccounts = rename_include_subaccounts;
+        state.watch_files = watch_files;
+        state.document_changes = document_changes;
+        Ok(())
+    }
+
+    async fn open_document(&self, item: TextDocumentItem) -> Result<()> {
+        let document = document::Document::new(item.text, item.version)?;
+        let mut state = self.state.write().await;
+        state.documents.insert(item.uri, document);
+        Ok(())
+    }
+
+    async fn change_document(&self, params: DidChangeTextDocumentParams) -> Result<()> {
+        let mut state = self.state.write().await;
+
+        let document = state
+            .documents
+            .get_mut(&params.text_document.uri)
+            .ok_or(Error::InvalidState)?;
+
+        document.apply_changes(params.content_changes, params.text_document.version)?;
+        Ok(())
+    }
+
+    async fn log_message<M: Display>(&self, typ: MessageType, message: M) {
+        if let Some(client) = &self.client {
+            client.log_message(typ, message).await;
+        }
+    }
+
+    async fn check(&self, uri: Url) -> Result<()> {
+        let check_cmd = self.check_cmd.as_ref().ok_or(Error::InvalidState)?;
+
+        let output = Command::new(check_cmd)
+            .arg(uri.path())
+            .output()
+            .await
+            .map_err(Error::from)?;
+
+        let diags = if !output.status.success() {
+            let output = std::str::from_utf8(&output.stderr).map_err(Error::from)?;
+
+            output
                 .lines()
                 .filter_map(|line| self.check_re.captures(line))
                 .map(|caps| {
@@ -212,151 +1260,522 @@ impl Backend {
             vec![]
         };
 
-        client.publish_diagnostics(uri, diags, None).await;
+        let mut state = self.state.write().await;
+        state.checks.insert(uri, diags);
+        Ok(())
+    }
+
+    /// Publish bean-check and index diagnostics, clearing those that are gone.
+    async fn publish_diagnostics(&self) -> Result<()> {
+        let client = self.client.as_ref().ok_or(Error::InvalidState)?;
+        let mut state = self.state.write().await;
+        let mut diagnostics = state.data.diagnostics();
+
+        for (uri, checks) in &state.checks {
+            diagnostics
+                .entry(uri.clone())
+                .or_default()
+                .extend(checks.iter().cloned());
+        }
+
+        for uri in state.published.drain() {
+            diagnostics.entry(uri).or_default();
+        }
+
+        state.published = diagnostics
+            .iter()
+            .filter(|(_, diags)| !diags.is_empty())
+            .map(|(uri, _)| uri.clone())
+            .collect();
+
+        drop(state);
+
+        for (uri, diags) in diagnostics {
+            client.publish_diagnostics(uri, diags, None).await;
+        }
+
         Ok(())
     }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        self.configure(params).await?;
+
         Ok(InitializeResult {
             server_info: Some(ServerInfo {
                 name: "beancount-language-server".to_string(),
                 version: Some(env!("CARGO_PKG_VERSION").to_string()),
             }),
             capabilities: ServerCapabilities {
-                // TODO: incremental is probably smarter
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: Some(vec![":".to_string()]),
+                    resolve_provider: Some(true),
+                    trigger_characters: Some(vec![
+                        " ".to_string(),
+                        "/".to_string(),
+                        ":".to_string(),
+                        "#".to_string(),
+                        "^".to_string(),
+                    ]),
                     work_done_progress_options: Default::default(),
                     all_commit_characters: None,
                 }),
                 definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
                 document_formatting_provider: Some(OneOf::Left(true)),
                 ..Default::default()
             },
         })
     }
 
-    async fn initialized(&self, _: InitializedParams) {}
+    async fn initialized(&self, _: InitializedParams) {
+        if let Err(err) = self.watch_ledgers().await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+    }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        if let Err(err) = self.load_ledgers(&params.text_document.uri).await {
+        let uri = params.text_document.uri.clone();
+
+        if let Err(err) = self.open_document(params.text_document).await {
             self.log_message(MessageType::ERROR, err.to_string()).await;
         }
 
-        if let Err(err) = self.check(params.text_document.uri).await {
+        if let Err(err) = self.load_ledgers(&uri).await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+
+        if let Err(err) = self.watch_ledgers().await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+
+        if let Err(err) = self.check(uri).await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+
+        if let Err(err) = self.publish_diagnostics().await {
             self.log_message(MessageType::ERROR, err.to_string()).await;
         }
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let mut state = self.state.write().await;
-        state.data.text = params.content_changes[0].text.clone();
+        if let Err(err) = self.change_document(params).await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
     }
 
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if let Err(err) = self.reload_ledger(&params.text_document.uri).await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+
+        if let Err(err) = self.watch_ledgers().await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+
         if let Err(err) = self.check(params.text_document.uri).await {
             self.log_message(MessageType::ERROR, err.to_string()).await;
         }
+
+        if let Err(err) = self.publish_diagnostics().await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let mut state = self.state.write().await;
+        state.documents.remove(&params.text_document.uri);
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        for change in params.changes {
+            if let Err(err) = self.reload_ledger(&change.uri).await {
+                self.log_message(MessageType::ERROR, err.to_string()).await;
+            }
+        }
+
+        if let Err(err) = self.watch_ledgers().await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
+
+        if let Err(err) = self.publish_diagnostics().await {
+            self.log_message(MessageType::ERROR, err.to_string()).await;
+        }
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         let state = self.state.read().await;
 
-        let mut parser = tree_sitter::Parser::new();
-        parser.set_language(self.language).map_err(Error::from)?;
+        let uri = &params.text_document_position.text_document.uri;
 
-        let tree = parser
-            .parse(&state.data.text, None)
-            .ok_or(Error::TreeParseError)?;
+        let document = match state.documents.get(uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
 
-        let line = params.text_document_position.position.line as usize;
-        let char = params.text_document_position.position.character as usize;
+        let position = params.text_document_position.position;
+        let end = document.point(position);
 
-        let start = tree_sitter::Point {
-            row: line,
-            column: if char == 0 { char } else { char - 1 },
-        };
+        let line = document.line_before(position);
+        let directive = beancount::directive_at(&document.tree, end.row);
+
+        let date = directive
+            .and_then(|directive| directive.child_by_field_name("date"))
+            .and_then(|date| date.utf8_text(document.text.as_bytes()).ok());
+
+        if line.trim().is_empty() {
+            let transaction = directive.filter(|directive| {
+                directive.kind() == "transaction" && directive.start_position().row + 1 == end.row
+            });
+
+            if let Some(transaction) = transaction {
+                return state.complete_postings(&transaction, &document.text, position);
+            }
+        }
+
+        if let Some((keyword, prefix)) = path_prefix(line) {
+            return state.complete_path(uri, keyword, prefix, position);
+        }
+
+        if let Some((name, prefix)) = option_prefix(line) {
+            return state.complete_option(name, prefix, position);
+        }
+
+        if let Some(prefix) = keyword_prefix(line) {
+            return state.complete_keyword(prefix, position);
+        }
+
+        if let Some((payee, prefix)) = narration_prefix(line) {
+            return state.complete_narration(payee, prefix, position);
+        }
+
+        // Metadata keys and account queries look alike, e.g. `e:f:`, so only complete metadata
+        // if there are candidates.
+        if let (Some(prefix), Some(directive)) = (metadata_prefix(line), directive) {
+            if let Some(response) = state.complete_metadata(prefix, directive.kind())? {
+                return Ok(Some(response));
+            }
+        }
+
+        if let Some((account, prefix)) = currency_prefix(line) {
+            return state.complete_currency(Some(account), prefix, position);
+        }
+
+        // The first word of an indented line is the account of a posting.
+        let trimmed = line.trim_start();
 
-        let end = tree_sitter::Point {
-            row: line,
-            column: char,
+        if trimmed.len() != line.len()
+            && !trimmed.is_empty()
+            && !trimmed.contains(char::is_whitespace)
+        {
+            return state.complete_account(uri, trimmed, position, date);
+        }
+
+        let start = tree_sitter::Point {
+            row: end.row,
+            column: end.column.saturating_sub(1),
         };
 
-        let is_character_triggered = params
-            .context
-            .and_then(|c| c.trigger_character)
-            .and_then(|c| if c == ":" { Some(()) } else { None })
-            .is_some();
+        let trigger_character = params.context.and_then(|c| c.trigger_character);
 
-        let node = tree
+        let node = document
+            .tree
             .root_node()
             .named_descendant_for_point_range(start, end);
 
         match node {
-            Some(node) => {
-                if is_character_triggered {
-                    state.complete_account()
-                } else {
-                    state.handle_node(&node)
-                }
-            }
+            Some(node) => match trigger_character.as_deref() {
+                Some(" ") | Some("/") => Ok(None),
+                Some(":") => state.complete_account(uri, account_query(line), position, date),
+                Some("#") if node.kind() != "string" => state.complete_ranked(SymbolKind::Tag),
+                Some("^") if node.kind() != "string" => state.complete_ranked(SymbolKind::Link),
+                _ => state.handle_node(uri, &node, &document.text, line, position, date),
+            },
             None => Ok(None),
         }
     }
 
+    async fn completion_resolve(&self, item: CompletionItem) -> Result<CompletionItem> {
+        Ok(self.state.read().await.resolve(item))
+    }
+
     async fn goto_definition(
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
         let state = self.state.read().await;
 
-        let mut parser = tree_sitter::Parser::new();
-        parser.set_language(self.language).map_err(Error::from)?;
-
-        let tree = parser
-            .parse(&state.data.text, None)
-            .ok_or(Error::TreeParseError)?;
+        let document = match state
+            .documents
+            .get(&params.text_document_position_params.text_document.uri)
+        {
+            Some(document) => document,
+            None => return Ok(None),
+        };
 
-        let line = params.text_document_position_params.position.line as usize;
-        let char = params.text_document_position_params.position.character as usize;
+        let position = params.text_document_position_params.position;
+        let point = document.point(position);
+
+        let locations = linked_paths(
+            &params.text_document_position_params.text_document.uri,
+            document,
+        )?
+        .into_iter()
+        .find(|(range, _)| range.start <= position && position <= range.end)
+        .map(|(_, files)| {
+            files
+                .into_iter()
+                .map(|uri| Location {
+                    uri,
+                    range: Range::default(),
+                })
+                .collect::<Vec<_>>()
+        });
 
-        let point = tree_sitter::Point {
-            row: line,
-            column: char,
-        };
+        if let Some(locations) = locations.filter(|locations| !locations.is_empty()) {
+            return Ok(Some(GotoDefinitionResponse::Array(locations)));
+        }
 
-        if let Some(node) = tree
+        if let Some(node) = document
+            .tree
             .root_node()
             .named_descendant_for_point_range(point, point)
         {
             if node.kind() == "currency" {
-                return Ok(state
+                let locations = state
                     .data
-                    .commodities
-                    .get(node_text(&node, &state.data.text)?)
-                    .map(|loc| GotoDefinitionResponse::Array(vec![loc.clone()])));
+                    .definitions(SymbolKind::Commodity, node_text(&node, &document.text)?);
+
+                if !locations.is_empty() {
+                    return Ok(Some(GotoDefinitionResponse::Array(locations)));
+                }
+            }
+
+            if node.kind() == "account" {
+                let name = node_text(&node, &document.text)?;
+                let locations = state.data.definitions(SymbolKind::Account, name);
+
+                if !locations.is_empty() {
+                    return Ok(Some(GotoDefinitionResponse::Array(locations)));
+                }
+
+                // Accounts used without being opened are defined by their earliest use.
+                if let Some(location) = state.data.first_use(SymbolKind::Account, name) {
+                    return Ok(Some(GotoDefinitionResponse::Scalar(location)));
+                }
             }
         }
 
         Ok(None)
     }
 
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let state = self.state.read().await;
+
+        let document = match state.documents.get(&params.text_document.uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+
+        // A link has a single target, so globs matching several files are left out.
+        let links = linked_paths(&params.text_document.uri, document)?
+            .into_iter()
+            .filter(|(_, files)| files.len() == 1)
+            .map(|(range, mut files)| DocumentLink {
+                range,
+                target: files.pop(),
+                tooltip: None,
+                data: None,
+            })
+            .collect();
+
+        Ok(Some(links))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let state = self.state.read().await;
+
+        let document = match state
+            .documents
+            .get(&params.text_document_position.text_document.uri)
+        {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+
+        let point = document.point(params.text_document_position.position);
+
+        let symbol = document
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)
+            .and_then(|node| symbol_at(&node, &document.text));
+
+        let (kind, name) = match symbol {
+            Some(symbol) => symbol,
+            None => return Ok(None),
+        };
+
+        let locations = state.data.references(
+            kind,
+            &name,
+            params.context.include_declaration,
+            state.references_include_subaccounts,
+        );
+
+        Ok(Some(locations))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let state = self.state.read().await;
+
+        let document = match state.documents.get(&params.text_document.uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+
+        let point = document.point(params.position);
+
+        let node = match document
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)
+        {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+
+        match symbol_at(&node, &document.text) {
+            Some((
+                kind @ (SymbolKind::Account | SymbolKind::Commodity | SymbolKind::Payee),
+                name,
+            )) => {
+                let mut range = beancount::range(&node, &document.text);
+
+                // The placeholder of a payee lacks the quotes of its string.
+                if kind == SymbolKind::Payee {
+                    range.start.character += 1;
+                    range.end.character -= 1;
+                }
+
+                Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+                    range,
+                    placeholder: name,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let state = self.state.read().await;
+
+        let document = match state
+            .documents
+            .get(&params.text_document_position.text_document.uri)
+        {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+
+        let point = document.point(params.text_document_position.position);
+
+        let symbol = document
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)
+            .and_then(|node| symbol_at(&node, &document.text));
+
+        let (kind, name) = match symbol {
+            Some(symbol) => symbol,
+            None => return Ok(None),
+        };
+
+        // The index is keyed by normalized URIs, open documents by those of the client.
+        let documents = state
+            .documents
+            .iter()
+            .map(|(uri, document)| (beancount::normalize_url(uri), (uri, document)))
+            .collect::<HashMap<_, _>>();
+
+        let symbols = documents
+            .iter()
+            .map(|(uri, (_, document))| {
+                (
+                    uri.clone(),
+                    beancount::symbols(&document.tree, &document.text),
+                )
+            })
+            .collect();
+
+        let changes = state.data.rename(
+            &beancount::normalize_url(&params.text_document_position.text_document.uri),
+            kind,
+            &name,
+            &params.new_name,
+            state.rename_include_subaccounts,
+            &symbols,
+        )?;
+
+        // Edits of open documents refer to them by the URI and version known to the client.
+        let changes = changes
+            .into_iter()
+            .map(|(uri, edits)| match documents.get(&uri) {
+                Some((uri, document)) => ((*uri).clone(), Some(document.version), edits),
+                None => (uri, None, edits),
+            });
+
+        if !state.document_changes {
+            return Ok(Some(WorkspaceEdit {
+                changes: Some(changes.map(|(uri, _, edits)| (uri, edits)).collect()),
+                ..WorkspaceEdit::default()
+            }));
+        }
+
+        // Versions let the client reject edits of documents changed in the meantime.
+        let edits = changes
+            .map(|(uri, version, edits)| TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri, version },
+                edits: edits.into_iter().map(OneOf::Left).collect(),
+            })
+            .collect();
+
+        Ok(Some(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(edits)),
+            ..WorkspaceEdit::default()
+        }))
+    }
+
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
         // Lets use brute force and delete everything and add the newly formatted stuff back.
         let state = self.state.read().await;
-        let formatted = beancount::reformat(&params.text_document.uri)?.map(|formatted| {
+
+        let document = state
+            .documents
+            .get(&params.text_document.uri)
+            .ok_or(Error::InvalidState)?;
+
+        let formatted = beancount::reformat(&document.text, &document.tree)?.map(|formatted| {
             vec![TextEdit {
                 range: Range {
                     start: Position::default(),
                     end: Position {
-                        line: state.data.text.matches('\n').count() as u32,
+                        line: document.text.matches('\n').count() as u32,
                         character: 0,
                     },
                 },
@@ -391,7 +1810,6 @@ mod tests {
                 client: None,
                 check_cmd: None,
                 check_re: regex::Regex::new(r"").expect("unable to build empty regex"),
-                language: tree_sitter_beancount::language(),
                 state: Arc::new(RwLock::new(State::default())),
             }
         }
@@ -401,33 +1819,40 @@ mod tests {
         Ok(Url::from_file_path(path).map_err(|_| Error::UriToPathConversion)?)
     }
 
-    #[ignore]
-    #[tokio::test]
-    async fn complete_root_account() -> std::result::Result<(), Error> {
-        let mut file = tempfile::NamedTempFile::new()?;
+    async fn open(backend: &Backend, path: &Path) -> std::result::Result<Url, Error> {
+        let uri = url_from_file_path(path)?;
 
-        write!(
-            file.as_file_mut(),
-            r#"2021-07-11 "foo" "bar"
-  Expe
-        "#
-        )?;
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: uri.clone(),
+                    language_id: "beancount".to_string(),
+                    version: 0,
+                    text: std::fs::read_to_string(path)?,
+                },
+            })
+            .await;
 
-        let backend = Backend::new_without_client();
-        let uri = url_from_file_path(file.path())?;
-        backend.load_ledgers(&uri).await.unwrap();
+        Ok(uri)
+    }
 
-        let params = CompletionParams {
+    fn completion_params(
+        uri: Url,
+        line: u32,
+        character: u32,
+        trigger_character: Option<&str>,
+    ) -> CompletionParams {
+        CompletionParams {
             text_document_position: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier { uri },
-                position: Position {
-                    line: 1,
-                    character: 5,
-                },
+                position: Position { line, character },
             },
             context: Some(CompletionContext {
-                trigger_kind: CompletionTriggerKind::INVOKED,
-                trigger_character: None,
+                trigger_kind: match trigger_character {
+                    Some(_) => CompletionTriggerKind::TRIGGER_CHARACTER,
+                    None => CompletionTriggerKind::INVOKED,
+                },
+                trigger_character: trigger_character.map(str::to_string),
             }),
             work_done_progress_params: WorkDoneProgressParams {
                 work_done_token: None,
@@ -435,28 +1860,64 @@ mod tests {
             partial_result_params: PartialResultParams {
                 partial_result_token: None,
             },
-        };
-
-        let result = backend.completion(params).await.unwrap().unwrap();
-
-        match result {
-            CompletionResponse::Array(items) => {
-                assert_eq!(items.len(), 1);
-                assert_eq!(items[0].label, "Expenses");
-            }
-            _ => assert!(false),
-        };
-
-        Ok(())
+        }
     }
 
     #[tokio::test]
-    async fn complete_payee() -> std::result::Result<(), Error> {
+    async fn complete_root_account() -> std::result::Result<(), Error> {
         let mut file = tempfile::NamedTempFile::new()?;
 
         write!(
             file.as_file_mut(),
-            r#"2021-07-11 "foo" "bar"
+            r#"option "name_expenses" "Ausgaben"
+
+2021-07-11 * "foo" "bar"
+  Aus
+  Inc
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri.clone(), 3, 5, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::List(list) => {
+                assert_eq!(list.items.len(), 1);
+                assert_eq!(list.items[0].label, "Ausgaben");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        let result = backend
+            .completion(completion_params(uri, 4, 5, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::List(list) => {
+                assert_eq!(list.items.len(), 1);
+                assert_eq!(list.items[0].label, "Income");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_payee() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-07-11 "foo" "bar"
   Expenses:Foo:Bar
 2021-07-11 "faa" "bar"
   Expenses:Foo:Bar
@@ -467,8 +1928,7 @@ mod tests {
         )?;
 
         let backend = Backend::new_without_client();
-        let uri = url_from_file_path(file.path())?;
-        backend.load_ledgers(&uri).await.unwrap();
+        let uri = open(&backend, file.path()).await?;
 
         let params = CompletionParams {
             text_document_position: TextDocumentPositionParams {
@@ -505,4 +1965,1404 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn complete_payee_with_multiple_documents() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let first_path = dir.path().join("first.beancount");
+        let mut first = std::fs::File::create(&first_path)?;
+
+        write!(
+            first,
+            r#"2021-07-11 "foo" "bar"
+  Expenses:Foo:Bar
+2021-07-11 "faa" "bar"
+  Expenses:Foo:Bar
+2021-07-11 "f
+        "#
+        )?;
+
+        let second_path = dir.path().join("second.beancount");
+        let mut second = std::fs::File::create(&second_path)?;
+
+        write!(
+            second,
+            r#"2021-07-12 "baz" "qux"
+  Expenses:Foo:Bar
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, &first_path).await?;
+        open(&backend, &second_path).await?;
+
+        let result = backend
+            .completion(completion_params(uri, 4, 12, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 2);
+
+                for item in items {
+                    assert!(item.label == "foo" || item.label == "faa");
+                }
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_payee_from_root_ledger() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
+
+        let mut main = std::fs::File::create(dir.path().join("main.beancount"))?;
+
+        write!(
+            main,
+            r#"include "2024/expenses.beancount"
+
+2021-07-11 "foo" "bar"
+  Expenses:Foo:Bar
+2021-07-11 "faa" "bar"
+  Expenses:Foo:Bar
+"#
+        )?;
+
+        let expenses_path = dir.path().join("2024").join("expenses.beancount");
+        let mut expenses = std::fs::File::create(&expenses_path)?;
+
+        write!(
+            expenses,
+            r#"2024-01-01 "gaa" "bar"
+  Expenses:Foo:Bar
+2024-01-02 "f
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+
+        backend
+            .initialize(InitializeParams {
+                root_uri: Some(url_from_file_path(dir.path())?),
+                ..InitializeParams::default()
+            })
+            .await
+            .unwrap();
+
+        let uri = open(&backend, &expenses_path).await?;
+
+        let result = backend
+            .completion(completion_params(uri, 2, 12, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 2);
+
+                for item in items {
+                    assert!(item.label == "foo" || item.label == "faa");
+                }
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_tag() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"pushtag #berlin
+
+2021-07-10 * "foo" "bar" #trip
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR
+
+poptag #berlin
+
+2021-07-11 * "foo" "bar" #trip
+  Expenses:Cash       100.00 EUR
+  Assets:Checking    -100.00 EUR
+
+2021-07-12 * "foo" "bar" #
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri, 12, 26, Some("#")))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[0].label, "trip");
+                assert_eq!(items[1].label, "berlin");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_metadata() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking EUR
+  institution: "bank"
+
+2021-07-10 * "foo" "bar"
+  category: "food"
+  Expenses:Food       100.00 EUR
+  Assets:Checking    -100.00 EUR
+
+2021-07-11 * "baz" "qux"
+  category: "rent"
+  Expenses:Rent       100.00 EUR
+  Assets:Checking    -100.00 EUR
+
+2021-07-12 * "baz" "qux"
+  category: "rent"
+  cat
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri.clone(), 15, 5, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 1);
+                assert_eq!(items[0].label, "category");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        let result = backend
+            .completion(completion_params(uri, 14, 11, Some(":")))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[0].label, "\"rent\"");
+                assert_eq!(items[1].label, "\"food\"");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_narration() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-01-01 * "Landlord" "Rent January"
+  Expenses:Rent       500.00 EUR
+  Assets:Checking    -500.00 EUR
+
+2021-01-02 * "Hardware Store" "Replacement part"
+  Expenses:Home        20.00 EUR
+  Assets:Checking     -20.00 EUR
+
+2021-01-03 * "Hardware Store" "Replacement part"
+  Expenses:Home        20.00 EUR
+  Assets:Checking     -20.00 EUR
+
+2021-02-01 * "Landlord" "Re
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri, 12, 27, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[0].label, "Rent January");
+                assert_eq!(items[1].label, "Replacement part");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_postings() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-01-01 * "Landlord" "Rent January"
+  Expenses:Rent       500.00 EUR
+  Assets:Checking    -500.00 EUR
+
+2021-02-01 * "Landlord" "Rent February" #home
+  Expenses:Rent       510.00 EUR
+  Assets:Checking
+
+2021-03-01 * "Landlord" "Rent March"
+  
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri, 8, 2, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 1);
+                assert_eq!(items[0].label, "Landlord");
+
+                match &items[0].text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => assert_eq!(
+                        edit.new_text,
+                        "  Expenses:Rent  ${1:510.00} EUR\n  Assets:Checking"
+                    ),
+                    _ => panic!("unexpected text edit"),
+                }
+
+                let edits = items[0].additional_text_edits.as_ref().unwrap();
+                assert_eq!(edits.len(), 1);
+                assert_eq!(edits[0].range.start.line, 7);
+                assert_eq!(edits[0].range.start.character, 36);
+                assert_eq!(edits[0].new_text, " #home");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_postings_unsaved_changes() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-01-01 * "Landlord" "Rent January"
+  Expenses:Rent       500.00 EUR
+  Assets:Checking    -500.00 EUR
+
+2021-02-01 * "Landlord" "Rent February"
+  Expenses:Rent       510.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 1,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: r#"2021-02-01 * "Landlord" "Rent February"
+  Expenses:Rent       510.00 EUR
+  Assets:Checking
+
+2021-03-01 * "Landlord" "Rent March"
+  
+"#
+                    .to_string(),
+                }],
+            })
+            .await;
+
+        let result = backend
+            .completion(completion_params(uri, 5, 2, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => match &items[0].text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => assert_eq!(
+                    edit.new_text,
+                    "  Expenses:Rent  ${1:510.00} EUR\n  Assets:Checking"
+                ),
+                _ => panic!("unexpected text edit"),
+            },
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn format_unsaved_changes() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        writeln!(file.as_file_mut(), r#"option   "title" "foo""#)?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 1,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: Some(Range {
+                        start: Position {
+                            line: 0,
+                            character: 18,
+                        },
+                        end: Position {
+                            line: 0,
+                            character: 21,
+                        },
+                    }),
+                    range_length: None,
+                    text: "bar".to_string(),
+                }],
+            })
+            .await;
+
+        let edits = backend
+            .formatting(DocumentFormattingParams {
+                text_document: TextDocumentIdentifier { uri },
+                options: FormattingOptions::default(),
+                work_done_progress_params: WorkDoneProgressParams {
+                    work_done_token: None,
+                },
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(edits.len(), 1);
+        assert!(edits[0].new_text.starts_with(r#"option "title" "bar""#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_account() {
+        assert_eq!(account_match("", "Expenses:Food:Groceries"), Some(0));
+        assert_eq!(account_match("e:f:gro", "Expenses:Food:Groceries"), Some(0));
+        assert_eq!(account_match("groc", "Expenses:Food:Groceries"), Some(0));
+        assert_eq!(account_match("Expenses:", "Expenses:Food"), Some(0));
+        assert_eq!(account_match("exfg", "Expenses:Food:Groceries"), Some(1));
+        assert_eq!(account_match("Expenses:", "Expenses"), None);
+        assert_eq!(account_match("a:c", "Expenses:Food:Groceries"), None);
+    }
+
+    #[tokio::test]
+    async fn complete_account() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-01-01 * "Bakery" "Bread"
+  Expenses:Food:Bakery        2.00 EUR
+  Assets:Checking
+
+2021-01-02 * "Supermarket" "Groceries"
+  Expenses:Food:Groceries    20.00 EUR
+  Assets:Checking
+
+2021-01-03 * "Supermarket" "Groceries"
+  Expenses:Food:Groceries    30.00 EUR
+  Assets:Checking
+
+2021-01-04 * "Supermarket" "Groceries"
+  e:f:
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri, 12, 6, Some(":")))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::List(list) => {
+                assert!(list.is_incomplete);
+                assert_eq!(list.items.len(), 2);
+                assert_eq!(list.items[0].label, "Expenses:Food:Groceries");
+                assert_eq!(list.items[1].label, "Expenses:Food:Bakery");
+                assert_eq!(list.items[0].filter_text.as_deref(), Some("e:f:"));
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_account_open_on_date() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking
+2015-01-01 open Assets:Savings
+2020-12-31 close Assets:Savings
+2022-01-01 open Assets:Brokerage
+
+2019-01-01 * "Bank" "Transfer"
+  Assets:Savings     100.00 EUR
+  Assets:Savings    -100.00 EUR
+
+2021-07-10 * "Bank" "Transfer"
+  Assets:
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri, 10, 9, Some(":")))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::List(list) => {
+                let labels = list
+                    .items
+                    .iter()
+                    .map(|item| item.label.as_str())
+                    .collect::<Vec<_>>();
+
+                assert_eq!(
+                    labels,
+                    vec!["Assets:Checking", "Assets:Savings", "Assets:Brokerage"]
+                );
+
+                assert!(list.items[0].tags.is_none());
+                assert!(list.items[1].tags.is_some());
+                assert!(list.items[2].tags.is_some());
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_keyword() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2024-03-01 
+2024-03-01 ba
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri.clone(), 0, 11, Some(" ")))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => assert_eq!(items.len(), DIRECTIVES.len()),
+            _ => panic!("unexpected completion response"),
+        };
+
+        let result = backend
+            .completion(completion_params(uri, 1, 13, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 1);
+                assert_eq!(items[0].label, "balance");
+
+                match &items[0].text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => {
+                        assert_eq!(edit.range.start.character, 11);
+                        assert_eq!(
+                            edit.new_text,
+                            "balance ${1:account} ${2:number} ${3:currency}"
+                        );
+                    }
+                    _ => panic!("unexpected text edit"),
+                }
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_option() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"option "name_
+option "booking_method" "FI
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let result = backend
+            .completion(completion_params(uri.clone(), 0, 13, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 5);
+                assert!(items.iter().all(|item| item.label.starts_with("name_")));
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        let result = backend
+            .completion(completion_params(uri, 1, 27, None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        match result {
+            CompletionResponse::Array(items) => {
+                assert_eq!(items.len(), 1);
+                assert_eq!(items[0].label, "FIFO");
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_path() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
+        std::fs::File::create(dir.path().join("2024").join("expenses.beancount"))?;
+        std::fs::File::create(dir.path().join("2024").join("receipt.pdf"))?;
+        std::fs::File::create(dir.path().join("accounts.beancount"))?;
+
+        let main_path = dir.path().join("main.beancount");
+        let mut main = std::fs::File::create(&main_path)?;
+
+        write!(
+            main,
+            r#"include "
+include "2024/
+2024-01-01 document Assets:Checking "2024/
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, &main_path).await?;
+
+        let labels = |result: Option<CompletionResponse>| match result {
+            Some(CompletionResponse::Array(items)) => {
+                items.into_iter().map(|item| item.label).collect::<Vec<_>>()
+            }
+            _ => panic!("unexpected completion response"),
+        };
+
+        let result = backend
+            .completion(completion_params(uri.clone(), 0, 9, None))
+            .await
+            .unwrap();
+
+        assert_eq!(labels(result), vec!["2024/", "accounts.beancount"]);
+
+        let result = backend
+            .completion(completion_params(uri.clone(), 1, 14, Some("/")))
+            .await
+            .unwrap();
+
+        assert_eq!(labels(result), vec!["expenses.beancount"]);
+
+        let result = backend
+            .completion(completion_params(uri, 2, 42, Some("/")))
+            .await
+            .unwrap();
+
+        assert_eq!(labels(result), vec!["expenses.beancount", "receipt.pdf"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_currency() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking EUR
+2015-01-01 open Assets:Brokerage
+2015-01-01 commodity CHF
+
+2021-07-10 * "foo" "bar"
+  Assets:Brokerage       10 USD
+  Assets:Checking    -10.00 EUR
+
+2021-07-11 * "foo" "bar"
+  Assets:Checking     10.00 
+  Assets:Brokerage     10 
+        "#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let labels = |result: Option<CompletionResponse>| match result {
+            Some(CompletionResponse::Array(items)) => items
+                .into_iter()
+                .map(|item| (item.label, item.tags.is_some()))
+                .collect::<Vec<_>>(),
+            _ => panic!("unexpected completion response"),
+        };
+
+        let result = backend
+            .completion(completion_params(uri.clone(), 8, 28, Some(" ")))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            labels(result),
+            vec![
+                ("EUR".to_string(), false),
+                ("CHF".to_string(), true),
+                ("USD".to_string(), true)
+            ]
+        );
+
+        let result = backend
+            .completion(completion_params(uri, 9, 26, Some(" ")))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            labels(result),
+            vec![
+                ("USD".to_string(), false),
+                ("CHF".to_string(), false),
+                ("EUR".to_string(), false)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_completion() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Assets:Checking EUR "FIFO"
+2015-01-01 commodity EUR
+  name: "Euro"
+
+2021-07-10 * "foo" "bar" #trip
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+
+2021-07-11 * "foo" "baz"
+  Expenses:Food        20.50 EUR
+  Assets:Checking     -20.50 EUR
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+        open(&backend, file.path()).await?;
+
+        let documentation = |item: CompletionItem| match item.documentation {
+            Some(Documentation::MarkupContent(content)) => content.value,
+            _ => panic!("unexpected documentation"),
+        };
+
+        let item = backend
+            .completion_resolve(CompletionItem {
+                data: Some(resolve_data("account", "Assets:Checking")),
+                ..item_from_str("Assets:Checking")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            documentation(item),
+            "Opened 2015-01-01\n\nCurrencies: EUR\n\nBooking: FIFO\n\nBalance: -120.50 EUR\n\nLast used 2021-07-11"
+        );
+
+        let item = backend
+            .completion_resolve(CompletionItem {
+                data: Some(resolve_data("payee", "foo")),
+                ..item_from_str("foo")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            documentation(item),
+            "```beancount\n2021-07-11 * \"foo\" \"baz\"\n  Expenses:Food  20.50 EUR\n  Assets:Checking  -20.50 EUR\n```"
+        );
+
+        let item = backend
+            .completion_resolve(CompletionItem {
+                data: Some(resolve_data("commodity", "EUR")),
+                ..item_from_str("EUR")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(documentation(item), "Euro");
+
+        Ok(())
+    }
+
+    fn definition_params(uri: Url, line: u32, character: u32) -> GotoDefinitionParams {
+        GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: Position { line, character },
+            },
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: PartialResultParams {
+                partial_result_token: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn goto_account_definition() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let accounts_path = dir.path().join("accounts.beancount");
+        let mut accounts = std::fs::File::create(&accounts_path)?;
+
+        writeln!(accounts, "2015-01-01 open Assets:Checking EUR")?;
+
+        let main_path = dir.path().join("main.beancount");
+        let mut main = std::fs::File::create(&main_path)?;
+
+        write!(
+            main,
+            r#"include "accounts.beancount"
+
+2021-07-10 * "foo" "bar"
+  Expenses:Food       100.00 EUR
+  Assets:Checking    -100.00 EUR
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, &main_path).await?;
+
+        let result = backend
+            .goto_definition(definition_params(uri.clone(), 4, 5))
+            .await
+            .unwrap();
+
+        match result {
+            Some(GotoDefinitionResponse::Array(locations)) => {
+                assert_eq!(locations.len(), 1);
+                assert_eq!(locations[0].uri, url_from_file_path(&accounts_path)?);
+                assert_eq!(locations[0].range.start.line, 0);
+            }
+            _ => panic!("unexpected definition response"),
+        };
+
+        let result = backend
+            .goto_definition(definition_params(uri.clone(), 3, 5))
+            .await
+            .unwrap();
+
+        match result {
+            Some(GotoDefinitionResponse::Scalar(location)) => {
+                assert_eq!(location.uri, uri);
+                assert_eq!(location.range.start.line, 3);
+            }
+            _ => panic!("unexpected definition response"),
+        };
+
+        Ok(())
+    }
+
+    fn reference_params(
+        uri: Url,
+        line: u32,
+        character: u32,
+        include_declaration: bool,
+    ) -> ReferenceParams {
+        ReferenceParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: Position { line, character },
+            },
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: PartialResultParams {
+                partial_result_token: None,
+            },
+            context: ReferenceContext {
+                include_declaration,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn find_references() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let main_path = dir.path().join("main.beancount");
+        let mut main = std::fs::File::create(&main_path)?;
+
+        write!(
+            main,
+            r#"include "transactions.beancount"
+
+2015-01-01 open Expenses:Food
+2015-01-01 open Expenses:Food:Groceries
+"#
+        )?;
+
+        let transactions_path = dir.path().join("transactions.beancount");
+        let mut transactions = std::fs::File::create(&transactions_path)?;
+
+        write!(
+            transactions,
+            r#"2021-07-10 * "foo" "bar" #trip
+  Expenses:Food:Groceries  100.00 EUR
+  Assets:Checking
+
+2021-07-11 * "foo" "bar" #trip
+  Expenses:Food             10.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+
+        backend
+            .initialize(InitializeParams {
+                root_uri: Some(url_from_file_path(dir.path())?),
+                initialization_options: Some(serde_json::json!({
+                    "references_include_subaccounts": true
+                })),
+                ..InitializeParams::default()
+            })
+            .await
+            .unwrap();
+
+        let main_uri = open(&backend, &main_path).await?;
+        let transactions_uri = open(&backend, &transactions_path).await?;
+
+        let locations = backend
+            .references(reference_params(main_uri.clone(), 2, 20, true))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(locations.len(), 4);
+        assert_eq!(locations[0].uri, main_uri);
+        assert_eq!(locations[2].uri, transactions_uri);
+
+        let locations = backend
+            .references(reference_params(transactions_uri.clone(), 0, 15, false))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[1].range.start.line, 4);
+
+        let locations = backend
+            .references(reference_params(transactions_uri, 0, 27, false))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(locations.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_references_through_parent_include() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
+
+        let accounts_path = dir.path().join("accounts.beancount");
+        let mut accounts = std::fs::File::create(&accounts_path)?;
+        writeln!(accounts, "2015-01-01 open Assets:Checking")?;
+
+        let mut main = std::fs::File::create(dir.path().join("2024").join("main.beancount"))?;
+
+        write!(
+            main,
+            r#"include "../accounts.beancount"
+
+2024-01-01 * "foo" "bar"
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+
+        backend
+            .initialize(InitializeParams {
+                root_uri: Some(url_from_file_path(dir.path())?),
+                ..InitializeParams::default()
+            })
+            .await
+            .unwrap();
+
+        let uri = open(&backend, &accounts_path).await?;
+
+        let locations = backend
+            .references(reference_params(uri.clone(), 0, 20, true))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[1].uri, uri);
+
+        Ok(())
+    }
+
+    fn rename_params(uri: Url, line: u32, character: u32, new_name: &str) -> RenameParams {
+        RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: Position { line, character },
+            },
+            new_name: new_name.to_string(),
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn rename_account() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let main_path = dir.path().join("main.beancount");
+        let mut main = std::fs::File::create(&main_path)?;
+
+        write!(
+            main,
+            r#"include "transactions.beancount"
+
+2015-01-01 open Expenses:Food
+2015-01-01 open Expenses:Food:Groceries
+2015-01-01 open Expenses:Meals
+"#
+        )?;
+
+        let transactions_path = dir.path().join("transactions.beancount");
+        let mut transactions = std::fs::File::create(&transactions_path)?;
+
+        write!(
+            transactions,
+            r#"2021-07-10 * "foo" "bar"
+  Expenses:Food:Groceries  100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+
+        backend
+            .initialize(InitializeParams {
+                root_uri: Some(url_from_file_path(dir.path())?),
+                initialization_options: Some(serde_json::json!({
+                    "rename_include_subaccounts": true
+                })),
+                ..InitializeParams::default()
+            })
+            .await
+            .unwrap();
+
+        let main_uri = open(&backend, &main_path).await?;
+        let transactions_uri = url_from_file_path(&transactions_path)?;
+
+        let response = backend
+            .prepare_rename(TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: main_uri.clone(),
+                },
+                position: Position {
+                    line: 2,
+                    character: 20,
+                },
+            })
+            .await
+            .unwrap();
+
+        match response {
+            Some(PrepareRenameResponse::RangeWithPlaceholder { range, placeholder }) => {
+                assert_eq!(placeholder, "Expenses:Food");
+                assert_eq!(range.start.character, 16);
+                assert_eq!(range.end.character, 29);
+            }
+            _ => panic!("unexpected prepare rename response"),
+        }
+
+        let edit = backend
+            .rename(rename_params(main_uri.clone(), 2, 20, "Expenses:Groceries"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let changes = edit.changes.unwrap();
+        assert_eq!(changes[&main_uri].len(), 2);
+        assert_eq!(changes[&transactions_uri].len(), 1);
+        assert_eq!(
+            changes[&transactions_uri][0].new_text,
+            "Expenses:Groceries:Groceries"
+        );
+
+        assert!(backend
+            .rename(rename_params(main_uri, 2, 20, "Expenses:Meals"))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_payee() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2021-07-10 * "foo" "bar"
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+
+2021-07-11 * "foo" "baz"
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, file.path()).await?;
+
+        let response = backend
+            .prepare_rename(TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position {
+                    line: 0,
+                    character: 15,
+                },
+            })
+            .await
+            .unwrap();
+
+        match response {
+            Some(PrepareRenameResponse::RangeWithPlaceholder { range, placeholder }) => {
+                assert_eq!(placeholder, "foo");
+                assert_eq!(range.start.character, 14);
+                assert_eq!(range.end.character, 17);
+            }
+            _ => panic!("unexpected prepare rename response"),
+        }
+
+        let edit = backend
+            .rename(rename_params(uri.clone(), 0, 15, "Foo Inc."))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let changes = edit.changes.unwrap();
+        assert_eq!(changes[&uri].len(), 2);
+        assert!(changes[&uri]
+            .iter()
+            .all(|edit| edit.new_text == "\"Foo Inc.\"" && edit.range.start.character == 13));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_unsaved_changes() -> std::result::Result<(), Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+
+        write!(
+            file.as_file_mut(),
+            r#"2015-01-01 open Expenses:Food
+
+2021-07-10 * "foo" "bar"
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+
+        backend
+            .initialize(InitializeParams {
+                capabilities: ClientCapabilities {
+                    workspace: Some(WorkspaceClientCapabilities {
+                        workspace_edit: Some(WorkspaceEditClientCapabilities {
+                            document_changes: Some(true),
+                            ..WorkspaceEditClientCapabilities::default()
+                        }),
+                        ..WorkspaceClientCapabilities::default()
+                    }),
+                    ..ClientCapabilities::default()
+                },
+                ..InitializeParams::default()
+            })
+            .await
+            .unwrap();
+
+        let uri = open(&backend, file.path()).await?;
+
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 1,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: Some(Range {
+                        start: Position {
+                            line: 0,
+                            character: 0,
+                        },
+                        end: Position {
+                            line: 0,
+                            character: 0,
+                        },
+                    }),
+                    range_length: None,
+                    text: "2015-01-01 open Assets:Checking\n".to_string(),
+                }],
+            })
+            .await;
+
+        let edit = backend
+            .rename(rename_params(uri.clone(), 1, 20, "Expenses:Groceries"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(edit.changes.is_none());
+
+        match edit.document_changes {
+            Some(DocumentChanges::Edits(edits)) => {
+                assert_eq!(edits.len(), 1);
+                assert_eq!(edits[0].text_document.uri, uri);
+                assert_eq!(edits[0].text_document.version, Some(1));
+
+                let mut lines = edits[0]
+                    .edits
+                    .iter()
+                    .map(|edit| match edit {
+                        OneOf::Left(edit) => edit.range.start.line,
+                        OneOf::Right(edit) => edit.text_edit.range.start.line,
+                    })
+                    .collect::<Vec<_>>();
+                lines.sort_unstable();
+                assert_eq!(lines, vec![1, 4]);
+            }
+            _ => panic!("unexpected document changes"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_through_parent_directory() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
+
+        let main_path = dir.path().join("main.beancount");
+        let mut main = std::fs::File::create(&main_path)?;
+
+        write!(
+            main,
+            r#"2015-01-01 open Expenses:Food
+
+2021-07-10 * "foo" "bar"
+  Expenses:Food       100.00 EUR
+  Assets:Checking
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(
+            &backend,
+            &dir.path().join("2024").join("..").join("main.beancount"),
+        )
+        .await?;
+
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 1,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: Some(Range {
+                        start: Position {
+                            line: 1,
+                            character: 0,
+                        },
+                        end: Position {
+                            line: 1,
+                            character: 0,
+                        },
+                    }),
+                    range_length: None,
+                    text: "2015-01-01 open Expenses:Food:Groceries\n".to_string(),
+                }],
+            })
+            .await;
+
+        let edit = backend
+            .rename(rename_params(uri.clone(), 0, 20, "Expenses:Meals"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let changes = edit.changes.unwrap();
+        assert_eq!(changes.len(), 1);
+
+        let mut lines = changes[&uri]
+            .iter()
+            .map(|edit| edit.range.start.line)
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        assert_eq!(lines, vec![0, 4]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn goto_and_link_paths() -> std::result::Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("2024"))?;
+        std::fs::File::create(dir.path().join("2024").join("01.beancount"))?;
+        std::fs::File::create(dir.path().join("2024").join("02.beancount"))?;
+        std::fs::File::create(dir.path().join("accounts.beancount"))?;
+        std::fs::File::create(dir.path().join("receipt.pdf"))?;
+
+        let main_path = dir.path().join("main.beancount");
+        let mut main = std::fs::File::create(&main_path)?;
+
+        write!(
+            main,
+            r#"include "accounts.beancount"
+include "2024/*.beancount"
+2024-01-01 document Assets:Checking "receipt.pdf"
+"#
+        )?;
+
+        let backend = Backend::new_without_client();
+        let uri = open(&backend, &main_path).await?;
+
+        let result = backend
+            .goto_definition(definition_params(uri.clone(), 1, 12))
+            .await
+            .unwrap();
+
+        match result {
+            Some(GotoDefinitionResponse::Array(locations)) => {
+                assert_eq!(locations.len(), 2);
+                assert!(locations[0].uri.path().ends_with("2024/01.beancount"));
+                assert!(locations[1].uri.path().ends_with("2024/02.beancount"));
+            }
+            _ => panic!("unexpected definition response"),
+        };
+
+        let links = backend
+            .document_link(DocumentLinkParams {
+                text_document: TextDocumentIdentifier { uri },
+                work_done_progress_params: WorkDoneProgressParams {
+                    work_done_token: None,
+                },
+                partial_result_params: PartialResultParams {
+                    partial_result_token: None,
+                },
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(links.len(), 2);
+        assert_eq!(
+            links[0].target,
+            Some(url_from_file_path(dir.path().join("accounts.beancount"))?)
+        );
+        assert_eq!(links[1].range.start.line, 2);
+        assert_eq!(
+            links[1].target,
+            Some(url_from_file_path(dir.path().join("receipt.pdf"))?)
+        );
+
+        Ok(())
+    }
 }